@@ -0,0 +1,304 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::schema::{tag_schema, ArgKind, BodyKind, TagSchema};
+
+/// Tag of an abbreviation with what goes inside it.
+#[derive(Debug, Clone)]
+struct Item {
+    schema: &'static TagSchema,
+    number: Option<String>,
+    text: Option<String>,
+    count: usize,
+    children: Vec<Item>,
+}
+
+/// Most times one `*` repeats a tag or a group.
+const MAX_COUNT: usize = 1000;
+
+/// Most tags one abbreviation expands to.
+const MAX_TAGS: usize = 10_000;
+
+/// Expands an Emmet-like abbreviation into Daleth, indenting nested tags with
+/// `indent`:
+///
+/// - `ul>-*3` nests tags with `>`
+/// - `h1+p` places siblings with `+`
+/// - `(ul>-)+p` groups with parentheses
+/// - `-*3` repeats a tag
+/// - `p{Hello}` gives a tag its text
+/// - `h2` or `footn1` sets a number argument
+pub fn expand(abbreviation: &str, indent: &str) -> Result<String, String> {
+    let mut chars = abbreviation.trim().chars().peekable();
+    let items = list(&mut chars)?;
+
+    if let Some(c) = chars.next() {
+        return Err(format!("unexpected `{c}`"));
+    }
+
+    let mut expanded = String::new();
+    for item in &items {
+        render(item, indent, 0, &mut expanded)?;
+    }
+
+    Ok(expanded.trim_end().to_owned())
+}
+
+/// `item ('+' item)*`
+fn list(chars: &mut Peekable<Chars>) -> Result<Vec<Item>, String> {
+    let mut items = group(chars)?;
+
+    while chars.next_if_eq(&'+').is_some() {
+        items.extend(group(chars)?);
+        check_size(&items, 1)?;
+    }
+
+    Ok(items)
+}
+
+/// Refuses `items` repeated `count` times when that makes too many tags.
+fn check_size(items: &[Item], count: usize) -> Result<(), String> {
+    match tag_count(items).and_then(|tags| tags.checked_mul(count)) {
+        Some(tags) if tags <= MAX_TAGS => Ok(()),
+        _ => Err(format!("expands to more than {MAX_TAGS} tags")),
+    }
+}
+
+/// Tags `items` expand to, or `None` on overflow.
+fn tag_count(items: &[Item]) -> Option<usize> {
+    items.iter().try_fold(0usize, |total, item| {
+        let tags = tag_count(&item.children)?.checked_add(1)?;
+        total.checked_add(tags.checked_mul(item.count)?)
+    })
+}
+
+/// `'(' list ')' | tag ('>' list)?`
+fn group(chars: &mut Peekable<Chars>) -> Result<Vec<Item>, String> {
+    if chars.next_if_eq(&'(').is_some() {
+        let items = list(chars)?;
+        if chars.next() != Some(')') {
+            return Err("unclosed `(`".to_owned());
+        }
+
+        let count = count(chars)?;
+        check_size(&items, count)?;
+        return Ok(items
+            .iter()
+            .cycle()
+            .take(items.len() * count)
+            .cloned()
+            .collect());
+    }
+
+    let mut item = tag(chars)?;
+    if chars.next_if_eq(&'>').is_some() {
+        item.children = list(chars)?;
+    }
+
+    let items = vec![item];
+    check_size(&items, 1)?;
+    Ok(items)
+}
+
+/// `name number? ('{' text '}')? ('*' count)?`
+fn tag(chars: &mut Peekable<Chars>) -> Result<Item, String> {
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_lowercase() || *c == '-') {
+        name.push(c);
+    }
+
+    let mut number = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        number.push(c);
+    }
+
+    let schema = match name.as_str() {
+        "" => return Err("expected a tag name".to_owned()),
+        "-" => tag_schema("el"),
+        name => tag_schema(name),
+    }
+    .ok_or_else(|| format!("unknown tag `{name}`"))?;
+
+    let text = match chars.next_if_eq(&'{') {
+        Some(_) => {
+            let text = chars.by_ref().take_while(|c| *c != '}').collect::<String>();
+            Some(text)
+        }
+        None => None,
+    };
+
+    Ok(Item {
+        schema,
+        number: (!number.is_empty()).then_some(number),
+        text,
+        count: count(chars)?,
+        children: vec![],
+    })
+}
+
+/// `('*' digits)?`
+fn count(chars: &mut Peekable<Chars>) -> Result<usize, String> {
+    if chars.next_if_eq(&'*').is_none() {
+        return Ok(1);
+    }
+
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+
+    if digits.is_empty() {
+        return Err("expected a count after `*`".to_owned());
+    }
+
+    match digits.parse() {
+        Ok(0) => Err("expected a count after `*`".to_owned()),
+        Ok(count) if count <= MAX_COUNT => Ok(count),
+        _ => Err(format!("`*{digits}` repeats more than {MAX_COUNT} times")),
+    }
+}
+
+fn render(item: &Item, indent: &str, depth: usize, out: &mut String) -> Result<(), String> {
+    let schema = item.schema;
+    let prefix = indent.repeat(depth);
+
+    let mut head = schema.name.to_owned();
+    let mut number = item.number.as_deref();
+
+    for argument in schema.arguments {
+        match (argument.kind, number.take()) {
+            (ArgKind::HeadingLevel, level) => head.push_str(level.unwrap_or("1")),
+            (ArgKind::TextOrNumber, Some(n)) => head.push_str(&format!(" {n}")),
+            (_, Some(n)) => {
+                return Err(format!(
+                    "`{}` takes no number argument, found {n}",
+                    schema.name
+                ))
+            }
+            (_, None) if argument.optional => {}
+            (_, None) => head.push_str(" \"\""),
+        }
+    }
+
+    if let Some(n) = number {
+        return Err(format!(
+            "`{}` takes no number argument, found {n}",
+            schema.name
+        ));
+    }
+
+    for _ in 0..item.count {
+        match (&item.text, item.children.is_empty()) {
+            (Some(_), false) => {
+                return Err(format!("`{}` cannot have both text and tags", schema.name))
+            }
+            (Some(text), true) if schema.body.accepts_text() => {
+                out.push_str(&text_line(&prefix, &head, schema, text));
+            }
+            (Some(_), true) => return Err(format!("`{}` cannot hold text", schema.name)),
+            (None, false) if schema.body.accepts_tags() => {
+                out.push_str(&format!("{prefix}{head} [\n"));
+                for child in &item.children {
+                    render(child, indent, depth + 1, out)?;
+                }
+                out.push_str(&format!("{prefix}]\n"));
+            }
+            (None, false) => return Err(format!("`{}` cannot hold tags", schema.name)),
+            (None, true) => out.push_str(&match schema.body {
+                BodyKind::Text | BodyKind::TextOrTags => text_line(&prefix, &head, schema, ""),
+                BodyKind::Tags => format!("{prefix}{head} [\n{prefix}]\n"),
+                BodyKind::None | BodyKind::OptionalTextOrTags => format!("{prefix}{head}\n"),
+            }),
+        }
+    }
+
+    Ok(())
+}
+
+/// A tag with a one-line body, using the `- text` shorthand for `el`.
+fn text_line(prefix: &str, head: &str, schema: &TagSchema, text: &str) -> String {
+    let line = match schema.name {
+        "el" => format!("{prefix}- {text}"),
+        _ => format!("{prefix}{head}: {text}"),
+    };
+
+    format!("{}\n", line.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nesting_and_siblings() {
+        assert_eq!(expand("ul>-", "    "), Ok("ul [\n    -\n]".to_owned()));
+        assert_eq!(expand("h1+p", "    "), Ok("h1:\np:".to_owned()));
+    }
+
+    #[test]
+    fn groups_and_repeats() {
+        assert_eq!(
+            expand("(ul>-)+p", "    "),
+            Ok("ul [\n    -\n]\np:".to_owned())
+        );
+        assert_eq!(
+            expand("ol>-*3", "  "),
+            Ok("ol [\n  -\n  -\n  -\n]".to_owned())
+        );
+        assert_eq!(
+            expand("(h2+p)*2", "    "),
+            Ok("h2:\np:\nh2:\np:".to_owned())
+        );
+    }
+
+    #[test]
+    fn text_and_number_arguments() {
+        assert_eq!(expand("p{Hello}", "    "), Ok("p: Hello".to_owned()));
+        assert_eq!(expand("footn1", "    "), Ok("footn 1:".to_owned()));
+        assert_eq!(expand("link", "    "), Ok("link \"\"".to_owned()));
+    }
+
+    #[test]
+    fn invalid_abbreviations_are_refused() {
+        assert_eq!(expand("nope", "    "), Err("unknown tag `nope`".to_owned()));
+        assert_eq!(expand("(ul", "    "), Err("unclosed `(`".to_owned()));
+        assert_eq!(
+            expand("br{Hi}", "    "),
+            Err("`br` cannot hold text".to_owned())
+        );
+        assert_eq!(
+            expand("-*", "    "),
+            Err("expected a count after `*`".to_owned())
+        );
+    }
+
+    #[test]
+    fn repeats_are_capped() {
+        assert!(expand("-*1000", "    ").is_ok());
+        assert_eq!(
+            expand("-*1001", "    "),
+            Err("`*1001` repeats more than 1000 times".to_owned())
+        );
+        assert_eq!(
+            expand("-*99999999999999999999999", "    "),
+            Err("`*99999999999999999999999` repeats more than 1000 times".to_owned())
+        );
+    }
+
+    #[test]
+    fn expansions_are_capped() {
+        assert!(expand("ul*10>-*999", "    ").is_ok());
+        assert_eq!(
+            expand("ul*11>-*1000", "    "),
+            Err("expands to more than 10000 tags".to_owned())
+        );
+        assert_eq!(
+            expand("((((-*1000)*1000)*1000)*1000)*1000", "    "),
+            Err("expands to more than 10000 tags".to_owned())
+        );
+        assert_eq!(
+            expand(&["-*1000"; 11].join("+"), "    "),
+            Err("expands to more than 10000 tags".to_owned())
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use chumsky::input::Input;
+use chumsky::Parser;
+use dalet::daleth::lexer::lexer;
+use dalet::daleth::parser::parser;
+use dalet::typed::{AlignArg, Body, Hl, NNArg, NNBody, Page, TNullArg, Tag};
+
+/// Untyped view of a [`Tag`], so tags can be compared and walked uniformly.
+#[derive(Debug, PartialEq)]
+pub struct Node {
+    pub name: &'static str,
+    pub arguments: Vec<String>,
+    pub body: NodeBody,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum NodeBody {
+    Text(String),
+    Tags(Vec<Node>),
+    Null,
+}
+
+pub fn parse(source: &str) -> Option<Vec<Node>> {
+    let tokens = lexer().parse(source).into_output()?;
+    let page: Page = parser()
+        .parse(tokens.as_slice().spanned((0..source.len()).into()))
+        .into_result()
+        .ok()?;
+
+    Some(page.data.iter().map(node).collect())
+}
+
+pub fn node(tag: &Tag) -> Node {
+    let (name, arguments, body) = match tag {
+        Tag::El(body) => ("el", vec![], nn_body(body)),
+        Tag::H(body, level) => ("h", vec![heading_level(level)], text(body)),
+        Tag::P(body) => ("p", vec![], nn_body(body)),
+        Tag::Br => ("br", vec![], NodeBody::Null),
+        Tag::Ul(tags) => ("ul", vec![], self::tags(tags)),
+        Tag::Ol(tags) => ("ol", vec![], self::tags(tags)),
+        Tag::Row(tags, align) => ("row", vec![align_arg(align)], self::tags(tags)),
+        Tag::Link(body, url) => ("link", vec![url.clone()], self::body(body)),
+        Tag::Navlink(body, url) => ("navlink", vec![url.clone()], self::body(body)),
+        Tag::Btn(body, url) => ("btn", vec![url.clone()], self::body(body)),
+        Tag::Navbtn(body, url) => ("navbtn", vec![url.clone()], self::body(body)),
+        Tag::Img(src) => ("img", vec![src.clone()], NodeBody::Null),
+        Tag::Table(tags) => ("table", vec![], self::tags(tags)),
+        Tag::Trow(tags) => ("trow", vec![], self::tags(tags)),
+        Tag::Tprow(tags) => ("tprow", vec![], self::tags(tags)),
+        Tag::Hr => ("hr", vec![], NodeBody::Null),
+        Tag::B(body) => ("b", vec![], text(body)),
+        Tag::I(body) => ("i", vec![], text(body)),
+        Tag::Bq(body) => ("bq", vec![], nn_body(body)),
+        Tag::Footlnk(id) => ("footlnk", vec![nn_arg(id)], NodeBody::Null),
+        Tag::Footn(body, id) => ("footn", vec![nn_arg(id)], text(body)),
+        Tag::A(id) => ("a", vec![nn_arg(id)], NodeBody::Null),
+        Tag::S(body) => ("s", vec![], text(body)),
+        Tag::Sup(body) => ("sup", vec![], text(body)),
+        Tag::Sub(body) => ("sub", vec![], text(body)),
+        Tag::Disc(body) => ("disc", vec![], nn_body(body)),
+        Tag::Block(body, align) => ("block", vec![align_arg(align)], nn_body(body)),
+        Tag::Carousel(tags) => ("carousel", vec![], self::tags(tags)),
+        Tag::Code(body, language) => ("code", t_null_arg(language), text(body)),
+        Tag::Pre(body) => ("pre", vec![], text(body)),
+        Tag::Meta(body, key) => ("meta", vec![key.clone()], text(body)),
+    };
+
+    Node {
+        name,
+        arguments,
+        body,
+    }
+}
+
+/// Tags whose text keeps its whitespace, unlike other text, which is read
+/// with runs of whitespace collapsed.
+const PREFORMATTED: [&str; 2] = ["code", "pre"];
+
+/// Lists the semantic differences between two trees, ignoring whitespace-only
+/// differences in text bodies outside preformatted tags.
+pub fn differences(left: &[Node], right: &[Node]) -> Vec<String> {
+    let mut differences = vec![];
+    compare_nodes(left, right, "", &mut differences);
+    differences
+}
+
+fn compare_nodes(left: &[Node], right: &[Node], path: &str, differences: &mut Vec<String>) {
+    if left.len() != right.len() {
+        differences.push(format!(
+            "{}: expected {} tags, found {}",
+            display_path(path),
+            left.len(),
+            right.len()
+        ));
+    }
+
+    for (i, (left, right)) in left.iter().zip(right).enumerate() {
+        let path = format!("{path}/{i}");
+
+        if left.name != right.name {
+            differences.push(format!(
+                "{path}: expected `{}`, found `{}`",
+                left.name, right.name
+            ));
+            continue;
+        }
+
+        if left.arguments != right.arguments {
+            differences.push(format!(
+                "{path}: `{}` arguments changed from {:?} to {:?}",
+                left.name, left.arguments, right.arguments
+            ));
+        }
+
+        match (&left.body, &right.body) {
+            (NodeBody::Text(l), NodeBody::Text(r)) => {
+                let equal = match PREFORMATTED.contains(&left.name) {
+                    true => l == r,
+                    false => l.split_whitespace().eq(r.split_whitespace()),
+                };
+
+                if !equal {
+                    differences.push(format!(
+                        "{path}: `{}` text changed from {l:?} to {r:?}",
+                        left.name
+                    ));
+                }
+            }
+            (NodeBody::Tags(l), NodeBody::Tags(r)) => compare_nodes(l, r, &path, differences),
+            (NodeBody::Null, NodeBody::Null) => {}
+            _ => differences.push(format!("{path}: `{}` body kind changed", left.name)),
+        }
+    }
+}
+
+fn display_path(path: &str) -> &str {
+    if path.is_empty() {
+        "/"
+    } else {
+        path
+    }
+}
+
+fn tags(tags: &[Tag]) -> NodeBody {
+    NodeBody::Tags(tags.iter().map(node).collect())
+}
+
+fn text(text: &str) -> NodeBody {
+    NodeBody::Text(text.to_owned())
+}
+
+fn body(body: &Body) -> NodeBody {
+    match body {
+        Body::Text(t) => text(t),
+        Body::Tags(t) => tags(t),
+        Body::Null => NodeBody::Null,
+    }
+}
+
+fn nn_body(body: &NNBody) -> NodeBody {
+    match body {
+        NNBody::Text(t) => text(t),
+        NNBody::Tags(t) => tags(t),
+    }
+}
+
+fn nn_arg(arg: &NNArg) -> String {
+    match arg {
+        NNArg::Text(t) => t.clone(),
+        NNArg::Number(n) => n.to_string(),
+    }
+}
+
+fn t_null_arg(arg: &TNullArg) -> Vec<String> {
+    match arg {
+        TNullArg::Text(t) => vec![t.clone()],
+        TNullArg::Null => vec![],
+    }
+}
+
+fn heading_level(level: &Hl) -> String {
+    (level.clone() as u8).to_string()
+}
+
+fn align_arg(align: &AlignArg) -> String {
+    match align {
+        AlignArg::Start => "start",
+        AlignArg::Center => "center",
+        AlignArg::End => "end",
+    }
+    .to_owned()
+}
@@ -0,0 +1,164 @@
+use std::path::Path;
+
+use ropey::Rope;
+use serde_json::json;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Url};
+
+use crate::config::Config;
+use crate::diagnostics::analyze;
+use crate::position::Encoding;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Human,
+    Github,
+    Json,
+}
+
+const USAGE: &str =
+    "usage: dalet_lsp --check [--verbose] [--format-output human|github|json] <files>...";
+
+/// Runs batch mode if `--check` is given, returning the process exit code.
+pub fn run(args: &[String]) -> Option<i32> {
+    if !args.iter().any(|arg| arg == "--check") {
+        return None;
+    }
+
+    let mut output = OutputFormat::Human;
+    let mut config = Config::default();
+    let mut files = vec![];
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => {}
+            "--verbose" => config.verbose_diagnostics = true,
+            "--format-output" => {
+                output = match args.next().map(String::as_str) {
+                    Some("human") => OutputFormat::Human,
+                    Some("github") => OutputFormat::Github,
+                    Some("json") => OutputFormat::Json,
+                    _ => {
+                        eprintln!("{USAGE}");
+                        return Some(2);
+                    }
+                }
+            }
+            file => files.push(file),
+        }
+    }
+
+    if files.is_empty() {
+        eprintln!("{USAGE}");
+        return Some(2);
+    }
+
+    let mut has_errors = false;
+    let mut json_output = vec![];
+
+    for file in files {
+        let source = match std::fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("{file}: {err}");
+                has_errors = true;
+                continue;
+            }
+        };
+
+        // Related information points at the file by URI
+        let Some(uri) = Path::new(file)
+            .canonicalize()
+            .ok()
+            .and_then(|path| Url::from_file_path(path).ok())
+        else {
+            eprintln!("{file}: cannot be turned into a URI");
+            has_errors = true;
+            continue;
+        };
+
+        let diagnostics = analyze(
+            &uri,
+            &source,
+            &Rope::from_str(&source),
+            Encoding::Utf16,
+            &config,
+            None,
+        );
+        has_errors |= diagnostics
+            .iter()
+            .any(|d| d.severity == Some(DiagnosticSeverity::ERROR));
+
+        for diagnostic in &diagnostics {
+            match output {
+                OutputFormat::Human => println!("{}", human(Path::new(file), diagnostic)),
+                OutputFormat::Github => println!("{}", github(Path::new(file), diagnostic)),
+                OutputFormat::Json => json_output.push(json!({
+                    "file": file,
+                    "diagnostic": diagnostic,
+                })),
+            }
+        }
+    }
+
+    if output == OutputFormat::Json {
+        println!("{}", serde_json::Value::Array(json_output));
+    }
+
+    Some(has_errors as i32)
+}
+
+fn severity_name(severity: Option<DiagnosticSeverity>) -> &'static str {
+    match severity {
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        Some(DiagnosticSeverity::INFORMATION) => "info",
+        Some(DiagnosticSeverity::HINT) => "hint",
+        _ => "error",
+    }
+}
+
+fn human(file: &Path, diagnostic: &Diagnostic) -> String {
+    let start = diagnostic.range.start;
+
+    format!(
+        "{}:{}:{}: {}: {}",
+        file.display(),
+        start.line + 1,
+        start.character + 1,
+        severity_name(diagnostic.severity),
+        diagnostic.message
+    )
+}
+
+/// Formats a diagnostic as a GitHub Actions workflow command, which GitHub
+/// renders as an inline annotation.
+fn github(file: &Path, diagnostic: &Diagnostic) -> String {
+    let command = match diagnostic.severity {
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        Some(DiagnosticSeverity::INFORMATION) | Some(DiagnosticSeverity::HINT) => "notice",
+        _ => "error",
+    };
+    let range = diagnostic.range;
+
+    format!(
+        "::{command} file={},line={},col={},endLine={},endColumn={}::{}",
+        escape_property(&file.display().to_string()),
+        range.start.line + 1,
+        range.start.character + 1,
+        range.end.line + 1,
+        range.end.character + 1,
+        escape_data(&diagnostic.message)
+    )
+}
+
+fn escape_data(data: &str) -> String {
+    data.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(property: &str) -> String {
+    escape_data(property)
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::ops::Range as ByteRange;
+use std::sync::OnceLock;
+
+use chumsky::Parser;
+use dalet::daleth::lexer::full_lexer;
+use dalet::daleth::lexer::types::Token;
+use dalet::daleth::types::Spanned;
+use serde_json::json;
+use tower_lsp::lsp_types::{CompletionItem, CompletionItemKind, Documentation, MarkupKind};
+
+use crate::formatter::{format, FormatOptions};
+use crate::hover::HoverBuilder;
+use crate::schema::{tag_schema, TAGS};
+use crate::tokens::trim_span;
+
+/// Lines before the cursor scanned for a multi-line text left open.
+pub const CONTEXT_LINES: usize = 20;
+
+/// What the cursor is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Context {
+    /// Where a tag can start, possibly after part of its name.
+    TagName,
+    Argument,
+    Text,
+    Comment,
+    /// After an argument or a tag name, where no new tag starts.
+    Other,
+}
+
+/// Judges the context at the end of `window`, a few lines ending at the
+/// cursor, without lexing the document. Only multi-line text carries over
+/// from one line to the next.
+pub fn context(window: &str) -> Context {
+    let mut multiline = false;
+    let mut context = Context::TagName;
+
+    for line in window.split('\n') {
+        context = if multiline {
+            Context::Text
+        } else {
+            match line.trim_start().starts_with('#') {
+                true => Context::Comment,
+                false => Context::TagName,
+            }
+        };
+        if context == Context::Comment {
+            continue;
+        }
+
+        let mut chars = line.chars().peekable();
+        let mut boundary = true;
+
+        while let Some(c) = chars.next() {
+            match (context, c) {
+                (Context::Text | Context::Argument, '\\') => {
+                    chars.next();
+                }
+                (Context::Text, '}') if multiline => {
+                    multiline = false;
+                    context = Context::Other;
+                }
+                (Context::Text, _) => {}
+                (Context::Argument, '"') => context = Context::Other,
+                (Context::Argument, _) => {}
+                (_, '"') => context = Context::Argument,
+                (_, '{') => {
+                    multiline = true;
+                    context = Context::Text;
+                }
+                (_, ':') => context = Context::Text,
+                (_, '-') if boundary => context = Context::Text,
+                (_, c) if c.is_whitespace() || c == '[' || c == ']' => {
+                    boundary = true;
+                    context = Context::TagName;
+                    continue;
+                }
+                (_, c) if c.is_ascii_alphanumeric() && boundary => context = Context::TagName,
+                (_, c) if c.is_ascii_alphanumeric() => {}
+                _ => context = Context::Other,
+            }
+            boundary = false;
+        }
+    }
+
+    context
+}
+
+/// Spans of the multi-line text tokens, which let completion recognize text
+/// regardless of the lines around the cursor.
+pub fn text_regions(source: &str, tokens: &[Spanned<Token>]) -> Vec<ByteRange<usize>> {
+    tokens
+        .iter()
+        .filter(|(token, _)| {
+            matches!(
+                token,
+                Token::MLText(_)
+                    | Token::MLMSText(_, _)
+                    | Token::MLRText(_)
+                    | Token::Paragraph(_)
+                    | Token::TableSyntax(_)
+            )
+        })
+        .map(|(_, span)| trim_span(source, *span))
+        .collect()
+}
+
+/// Item closing the innermost construct left open before the cursor, sorted
+/// ahead of everything else.
+pub fn close_item(prefix: impl IntoIterator<Item = char>) -> Option<CompletionItem> {
+    let (tag, closer) = open_construct(prefix)?;
+
+    Some(CompletionItem {
+        label: format!("close {tag}"),
+        kind: Some(CompletionItemKind::SNIPPET),
+        detail: Some(format!("Insert `{closer}`")),
+        insert_text: Some(closer.to_owned()),
+        sort_text: Some("0".to_owned()),
+        preselect: Some(true),
+        ..Default::default()
+    })
+}
+
+/// Part of a prefix the scan for open constructs is in.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Scan {
+    Code,
+    /// A one-line text or a comment, which end with the line.
+    Line,
+    /// An argument of the tag.
+    Argument(&'static str),
+    /// A multi-line text of the tag.
+    Text(&'static str),
+}
+
+/// The tag left open at the end of `prefix` and the text that closes it.
+///
+/// The prefix may not lex, and lexing it on every completion is slow in a
+/// large document, so it is scanned once the way the lexer splits it:
+/// arguments and multi-line texts first, then the brackets they leave.
+fn open_construct(prefix: impl IntoIterator<Item = char>) -> Option<(&'static str, &'static str)> {
+    let mut chars = prefix.into_iter().peekable();
+    let mut scan = Scan::Code;
+    let mut last_tag = None;
+    let mut open = vec![];
+    let mut word = String::new();
+
+    while let Some(c) = chars.next() {
+        if scan == Scan::Code && c.is_ascii_lowercase() {
+            word.push(c);
+            continue;
+        }
+        if !word.is_empty() {
+            last_tag = tag_schema(&word).map(|schema| schema.name).or(last_tag);
+            word.clear();
+        }
+
+        match (scan, c) {
+            (Scan::Line, '\n') => scan = Scan::Code,
+            (Scan::Line, _) => {}
+            (Scan::Argument(_) | Scan::Text(_), '\\') => {
+                chars.next();
+            }
+            (Scan::Argument(_), '"' | '\n') => scan = Scan::Code,
+            (Scan::Text(_), '}') => scan = Scan::Code,
+            (Scan::Argument(_) | Scan::Text(_), _) => {}
+            (Scan::Code, '#' | ':' | '-') => scan = Scan::Line,
+            (Scan::Code, '"') => scan = Scan::Argument(last_tag?),
+            (Scan::Code, '{') => {
+                let tag = match chars.peek() {
+                    Some('-') => "p",
+                    Some('>') => "table",
+                    _ => last_tag?,
+                };
+                scan = Scan::Text(tag);
+            }
+            (Scan::Code, '[') => match chars.next_if_eq(&'[') {
+                Some(_) => open.push(("el", "]]")),
+                None => open.push((last_tag?, "]")),
+            },
+            (Scan::Code, ']') => {
+                chars.next_if_eq(&']');
+                open.pop();
+            }
+            (Scan::Code, _) => {}
+        }
+    }
+
+    match scan {
+        Scan::Argument(tag) => Some((tag, "\"")),
+        Scan::Text(tag) => Some((tag, "}")),
+        Scan::Code | Scan::Line => open.last().copied(),
+    }
+}
+
+pub fn tag_items() -> Vec<CompletionItem> {
+    TAGS.iter()
+        .map(|schema| CompletionItem {
+            label: schema.name.to_owned(),
+            kind: Some(CompletionItemKind::KEYWORD),
+            detail: Some(schema.signature()),
+            data: Some(json!({ "tag": schema.name })),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Adds the tag documentation and its formatted example to a tag item.
+pub fn resolve(mut item: CompletionItem, kind: MarkupKind) -> CompletionItem {
+    let Some(schema) = item
+        .data
+        .as_ref()
+        .and_then(|data| data.get("tag"))
+        .and_then(|tag| tag.as_str())
+        .and_then(tag_schema)
+    else {
+        return item;
+    };
+
+    let mut builder = HoverBuilder::new(kind).tag(schema);
+
+    if let Some(example) = example(schema.name) {
+        builder = builder.text("**Example**").code(example);
+    }
+
+    item.documentation = Some(Documentation::MarkupContent(builder.build()));
+    item
+}
+
+/// Examples are formatted once and reused by every resolve.
+fn example(name: &str) -> Option<&'static str> {
+    static EXAMPLES: OnceLock<HashMap<&'static str, String>> = OnceLock::new();
+
+    EXAMPLES
+        .get_or_init(|| {
+            TAGS.iter()
+                .filter_map(|schema| {
+                    let example = schema.example?;
+                    let tokens = full_lexer().parse(example).into_result().ok()?;
+                    let formatted = format(&tokens, &FormatOptions::default());
+
+                    Some((schema.name, formatted.trim_end().to_owned()))
+                })
+                .collect()
+        })
+        .get(name)
+        .map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn documentation(tag: &str) -> String {
+        let item = tag_items()
+            .into_iter()
+            .find(|item| item.label == tag)
+            .unwrap();
+
+        match resolve(item, MarkupKind::Markdown).documentation {
+            Some(Documentation::MarkupContent(content)) => content.value,
+            documentation => panic!("unexpected documentation {documentation:?}"),
+        }
+    }
+
+    #[test]
+    fn resolve_adds_the_formatted_example() {
+        let documentation = documentation("ul");
+
+        assert!(documentation.starts_with("```daleth\nul"));
+        assert!(documentation
+            .ends_with("**Example**\n\n```daleth\nul [\n    - Apples\n    - Oranges\n]\n```"));
+    }
+
+    #[test]
+    fn resolve_without_an_example_omits_the_section() {
+        let documentation = documentation("hr");
+
+        assert!(documentation.contains("Horizontal rule"));
+        assert!(!documentation.contains("Example"));
+    }
+
+    #[test]
+    fn resolve_keeps_items_that_are_not_tags() {
+        let item = CompletionItem::new_simple("close p".to_owned(), String::new());
+
+        assert_eq!(resolve(item.clone(), MarkupKind::Markdown), item);
+    }
+
+    #[test]
+    fn every_example_lexes_and_is_documented() {
+        for schema in TAGS.iter() {
+            let Some(source) = schema.example else {
+                assert_eq!(example(schema.name), None, "example of `{}`", schema.name);
+                continue;
+            };
+
+            let tokens = full_lexer().parse(source).into_result();
+            assert!(tokens.is_ok(), "example of `{}` does not lex", schema.name);
+
+            let formatted = example(schema.name)
+                .unwrap_or_else(|| panic!("example of `{}` is missing", schema.name));
+            assert!(
+                documentation(schema.name)
+                    .ends_with(&format!("**Example**\n\n```daleth\n{formatted}\n```")),
+                "documentation of `{}`",
+                schema.name
+            );
+        }
+    }
+
+    fn closing(prefix: &str) -> Option<(String, String)> {
+        let item = close_item(prefix.chars())?;
+        Some((item.label, item.insert_text?))
+    }
+
+    fn closes(tag: &str, closer: &str) -> Option<(String, String)> {
+        Some((format!("close {tag}"), closer.to_owned()))
+    }
+
+    #[test]
+    fn close_item_closes_the_innermost_bracket() {
+        assert_eq!(
+            closing("row [\n    ul [\n        - Apples\n"),
+            closes("ul", "]")
+        );
+        assert_eq!(
+            closing("row [\n    ul [\n        - Apples\n    ]\n"),
+            closes("row", "]")
+        );
+    }
+
+    #[test]
+    fn close_item_closes_text_and_arguments_before_brackets() {
+        assert_eq!(
+            closing("row [\n    code \"rust\" {fn main"),
+            closes("code", "}")
+        );
+        assert_eq!(closing("row [\n    img \"a.png"), closes("img", "\""));
+        assert_eq!(closing("row [\n    {- Some"), closes("p", "}"));
+        assert_eq!(closing("{> table\n[[ A | B ]]"), closes("table", "}"));
+    }
+
+    #[test]
+    fn close_item_skips_brackets_in_text_and_comments() {
+        assert_eq!(closing("[[\n    p: Text ]\n"), closes("el", "]]"));
+        assert_eq!(
+            closing("ul [\n    # ]\n    code {\n]\n}\n"),
+            closes("ul", "]")
+        );
+        assert_eq!(closing("p \"[\" [\n    br\n]\n"), None);
+    }
+
+    #[test]
+    fn close_item_is_absent_when_everything_is_closed() {
+        assert_eq!(closing("row [\n    p: Text\n]\n"), None);
+    }
+}
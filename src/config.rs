@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::Value;
+use tower_lsp::lsp_types::{DiagnosticSeverity, Url};
+
+use crate::formatter::FormatOptions;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Config {
+    pub associated_extensions: Vec<String>,
+    /// Minutes without workspace requests after which the workspace index is
+    /// dropped. Zero keeps it loaded.
+    pub index_idle_unload_minutes: f64,
+    /// Reading speed used for the reading time shown when hovering containers.
+    pub reading_words_per_minute: f64,
+    pub align_meta_keys: bool,
+    pub sort_meta_keys: bool,
+    /// Deprecated alias of `features.semanticTokens`, used only when that is
+    /// not set.
+    pub enable_semantic_tokens: Option<bool>,
+    /// Turns off every feature that edits documents.
+    pub analysis_only: bool,
+    /// Severity per diagnostic code, or `off` to drop the diagnostics.
+    pub diagnostic_severity: HashMap<String, SeverityOverride>,
+    /// Appends the offending line, with carets under the range, to messages.
+    pub verbose_diagnostics: bool,
+    /// Most diagnostics published for one document.
+    pub max_diagnostics: usize,
+    /// Errors at most this many characters apart are published as one
+    /// diagnostic, with the others as related information.
+    pub coalesce_diagnostics: Option<usize>,
+    pub diagnostics_sink: DiagnosticsSink,
+    /// JSONL file that diagnostics are appended to when the sink includes `file`.
+    pub diagnostics_file: Option<PathBuf>,
+    pub features: Features,
+    /// Hints at anchors that no link in the workspace points at.
+    pub unused_anchors: bool,
+    /// Anchors linked from outside the workspace, never reported as unused.
+    /// A trailing `*` matches any suffix.
+    pub public_anchors: Vec<String>,
+    /// Languages `code` tags may declare. Empty accepts any language.
+    pub known_languages: Vec<String>,
+}
+
+/// Language features that can be turned off individually. A disabled feature
+/// is not advertised, and its requests get an empty result.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Features {
+    pub hover: bool,
+    pub completion: bool,
+    pub document_symbol: bool,
+    pub declaration: bool,
+    pub definition: bool,
+    pub folding_range: bool,
+    pub semantic_tokens: bool,
+    pub formatting: bool,
+    pub range_formatting: bool,
+    pub code_action: bool,
+    pub rename: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            hover: true,
+            completion: true,
+            document_symbol: true,
+            declaration: true,
+            definition: true,
+            folding_range: true,
+            semantic_tokens: true,
+            formatting: true,
+            range_formatting: true,
+            code_action: true,
+            rename: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SeverityOverride {
+    Error,
+    Warning,
+    Information,
+    Hint,
+    Off,
+}
+
+impl SeverityOverride {
+    pub fn severity(self) -> Option<DiagnosticSeverity> {
+        match self {
+            Self::Error => Some(DiagnosticSeverity::ERROR),
+            Self::Warning => Some(DiagnosticSeverity::WARNING),
+            Self::Information => Some(DiagnosticSeverity::INFORMATION),
+            Self::Hint => Some(DiagnosticSeverity::HINT),
+            Self::Off => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DiagnosticsSink {
+    Client,
+    File,
+    Both,
+}
+
+impl DiagnosticsSink {
+    pub fn client(self) -> bool {
+        self != Self::File
+    }
+
+    pub fn file(self) -> bool {
+        self != Self::Client
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            associated_extensions: vec!["dlt".to_owned()],
+            index_idle_unload_minutes: 0.0,
+            reading_words_per_minute: 200.0,
+            align_meta_keys: true,
+            sort_meta_keys: false,
+            enable_semantic_tokens: None,
+            analysis_only: false,
+            diagnostic_severity: HashMap::new(),
+            verbose_diagnostics: false,
+            max_diagnostics: 200,
+            coalesce_diagnostics: None,
+            diagnostics_sink: DiagnosticsSink::Client,
+            diagnostics_file: None,
+            features: Features::default(),
+            unused_anchors: false,
+            public_anchors: vec![],
+            known_languages: vec![],
+        }
+    }
+}
+
+impl Config {
+    /// Accepts both bare settings and settings nested under a `daleth` section.
+    pub fn from_value(value: Value) -> Option<Self> {
+        let value = match value {
+            Value::Object(mut map) if map.contains_key("daleth") => map.remove("daleth")?,
+            value => value,
+        };
+
+        let semantic_tokens_set = value.pointer("/features/semanticTokens").is_some();
+        let mut config: Self = serde_json::from_value(value).ok()?;
+
+        if let (Some(enabled), false) = (config.enable_semantic_tokens, semantic_tokens_set) {
+            config.features.semantic_tokens = enabled;
+        }
+
+        Some(config)
+    }
+
+    pub fn format_options(&self) -> FormatOptions {
+        FormatOptions {
+            align_meta: self.align_meta_keys,
+            sort_meta: self.sort_meta_keys,
+            ..FormatOptions::default()
+        }
+    }
+
+    pub fn is_public_anchor(&self, name: &str) -> bool {
+        self.public_anchors
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == pattern,
+            })
+    }
+
+    /// `None` keeps the index, also when the minutes do not fit a duration.
+    pub fn index_idle_unload(&self) -> Option<Duration> {
+        if self.index_idle_unload_minutes <= 0.0 {
+            return None;
+        }
+
+        Duration::try_from_secs_f64(self.index_idle_unload_minutes * 60.0).ok()
+    }
+
+    /// Glob matching the associated files, such as `**/*.{dlt}`.
+    pub fn glob(&self) -> String {
+        let extensions = self
+            .associated_extensions
+            .iter()
+            .map(|extension| extension.trim_start_matches('.'))
+            .collect::<Vec<_>>();
+
+        format!("**/*.{{{}}}", extensions.join(","))
+    }
+
+    pub fn is_associated(&self, uri: &Url) -> bool {
+        let extension = uri
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .and_then(|name| name.rsplit_once('.'))
+            .map(|(_, extension)| extension);
+
+        match extension {
+            Some(extension) => self.associated_extensions.iter().any(|associated| {
+                associated
+                    .trim_start_matches('.')
+                    .eq_ignore_ascii_case(extension)
+            }),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn idle_unload(minutes: f64) -> Option<Duration> {
+        Config {
+            index_idle_unload_minutes: minutes,
+            ..Config::default()
+        }
+        .index_idle_unload()
+    }
+
+    #[test]
+    fn idle_unload_is_converted_to_a_duration() {
+        assert_eq!(idle_unload(0.5), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn idle_unload_off_or_out_of_range_keeps_the_index() {
+        assert_eq!(idle_unload(0.0), None);
+        assert_eq!(idle_unload(-1.0), None);
+        assert_eq!(idle_unload(f64::NAN), None);
+        assert_eq!(idle_unload(f64::INFINITY), None);
+        assert_eq!(idle_unload(f64::MAX), None);
+    }
+
+    fn semantic_tokens(settings: Value) -> bool {
+        Config::from_value(settings)
+            .unwrap()
+            .features
+            .semantic_tokens
+    }
+
+    #[test]
+    fn enable_semantic_tokens_is_an_alias_of_the_feature() {
+        assert!(semantic_tokens(json!({})));
+        assert!(!semantic_tokens(json!({ "enableSemanticTokens": false })));
+        assert!(!semantic_tokens(
+            json!({ "daleth": { "enableSemanticTokens": false } })
+        ));
+        assert!(semantic_tokens(json!({
+            "enableSemanticTokens": false,
+            "features": { "semanticTokens": true },
+        })));
+        assert!(!semantic_tokens(json!({
+            "enableSemanticTokens": true,
+            "features": { "semanticTokens": false },
+        })));
+    }
+}
@@ -0,0 +1,873 @@
+use std::collections::HashSet;
+use std::ops::Range as ByteRange;
+
+use chumsky::input::Input;
+use chumsky::Parser;
+use dalet::daleth::lexer::{full_lexer, lexer};
+use dalet::daleth::parser::parser;
+use ropey::Rope;
+use serde_json::{json, Value};
+use tower_lsp::lsp_types::{
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, DiagnosticTag, Location,
+    NumberOrString, Range, Url,
+};
+
+use crate::config::{Config, SeverityOverride};
+use crate::position::{column_to_char, position_to_offset, span_to_range, Encoding};
+use crate::tree::{Node, Tree};
+
+/// Diagnostics of the document at `uri`, whose text is both `source` and
+/// `rope`. `referenced` holds the names of its
+/// anchors that some link points at, and enables the unused anchor lint.
+/// Columns are counted in `encoding`.
+pub fn analyze(
+    uri: &Url,
+    source: &str,
+    rope: &Rope,
+    encoding: Encoding,
+    config: &Config,
+    referenced: Option<&HashSet<String>>,
+) -> Vec<Diagnostic> {
+    let mut collector = DiagnosticCollector::new(uri, source, rope, encoding);
+
+    let (tokens, lex_errors) = lexer().parse(source).into_output_errors();
+
+    for error in lex_errors {
+        collector.push(
+            &LEX_ERROR,
+            error.span().into_range(),
+            error.to_string(),
+            None,
+        );
+    }
+
+    if let Some(tokens) = tokens {
+        let parse_errors = parser()
+            .parse(tokens.as_slice().spanned((0..source.len()).into()))
+            .into_errors();
+
+        for error in parse_errors {
+            collector.push(
+                &PARSE_ERROR,
+                error.span().into_range(),
+                error.to_string(),
+                None,
+            );
+        }
+    }
+
+    match full_lexer().parse(source).into_result() {
+        Ok(tokens) => {
+            let tree = Tree::new(source, &tokens);
+            check_arity(&tree.nodes, &mut collector);
+
+            if let Some(referenced) = referenced {
+                unused_anchors(source, &tree.nodes, referenced, config, &mut collector);
+            }
+
+            if !config.known_languages.is_empty() {
+                unknown_languages(&tree.nodes, config, &mut collector);
+            }
+        }
+        // A document that lexes has every typographic character inside text
+        Err(_) => {
+            for (span, found, expected) in typographic(source) {
+                collector.push(
+                    &TYPOGRAPHIC_CHARACTER,
+                    span,
+                    format!("typographic `{found}` where `{expected}` was expected"),
+                    Some(json!({ "replacement": expected.to_string() })),
+                );
+            }
+        }
+    }
+
+    collector.finish(config)
+}
+
+/// Kind of diagnostic a pass produces.
+struct Check {
+    source: &'static str,
+    code: &'static str,
+    severity: DiagnosticSeverity,
+    tags: &'static [DiagnosticTag],
+}
+
+const LEX_ERROR: Check = Check {
+    source: "daleth/lexer",
+    code: "lex-error",
+    severity: DiagnosticSeverity::ERROR,
+    tags: &[],
+};
+
+const PARSE_ERROR: Check = Check {
+    source: "daleth/parser",
+    code: "parse-error",
+    severity: DiagnosticSeverity::ERROR,
+    tags: &[],
+};
+
+const ARGUMENT_COUNT: Check = Check {
+    source: "daleth/schema",
+    code: "argument-count",
+    severity: DiagnosticSeverity::ERROR,
+    tags: &[],
+};
+
+const TYPOGRAPHIC_CHARACTER: Check = Check {
+    source: "daleth/lint",
+    code: TYPOGRAPHIC,
+    severity: DiagnosticSeverity::HINT,
+    tags: &[],
+};
+
+const UNUSED_ANCHOR_CHECK: Check = Check {
+    source: "daleth/lint",
+    code: UNUSED_ANCHOR,
+    severity: DiagnosticSeverity::HINT,
+    tags: &[DiagnosticTag::UNNECESSARY],
+};
+
+const UNKNOWN_LANGUAGE: Check = Check {
+    source: "daleth/lint",
+    code: "unknown-language",
+    severity: DiagnosticSeverity::HINT,
+    tags: &[],
+};
+
+/// Gathers the diagnostics of every pass so that severity overrides,
+/// suppressions, deduplication, sorting and the cap apply to all of them.
+struct DiagnosticCollector<'a> {
+    uri: &'a Url,
+    source: &'a str,
+    rope: &'a Rope,
+    encoding: Encoding,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> DiagnosticCollector<'a> {
+    fn new(uri: &'a Url, source: &'a str, rope: &'a Rope, encoding: Encoding) -> Self {
+        Self {
+            uri,
+            source,
+            rope,
+            encoding,
+            diagnostics: vec![],
+        }
+    }
+
+    fn push(
+        &mut self,
+        check: &Check,
+        span: ByteRange<usize>,
+        message: String,
+        data: Option<Value>,
+    ) {
+        let Some(range) = span_to_range(span, self.rope, self.encoding) else {
+            return;
+        };
+
+        self.diagnostics.push(Diagnostic {
+            range,
+            severity: Some(check.severity),
+            code: Some(NumberOrString::String(check.code.to_owned())),
+            source: Some(check.source.to_owned()),
+            message,
+            data,
+            tags: (!check.tags.is_empty()).then(|| check.tags.to_vec()),
+            ..Diagnostic::default()
+        });
+    }
+
+    fn finish(self, config: &Config) -> Vec<Diagnostic> {
+        let suppressed = suppressions(self.source);
+        let mut seen = HashSet::new();
+        let mut diagnostics = vec![];
+
+        for mut diagnostic in self.diagnostics {
+            let code = match &diagnostic.code {
+                Some(NumberOrString::String(code)) => code.as_str(),
+                _ => "",
+            };
+
+            let line = diagnostic.range.start.line as usize;
+            if suppressed
+                .iter()
+                .any(|(l, codes)| *l == line && (codes.is_empty() || codes.contains(&code)))
+            {
+                continue;
+            }
+
+            match config.diagnostic_severity.get(code) {
+                Some(SeverityOverride::Off) => continue,
+                Some(severity) => diagnostic.severity = severity.severity(),
+                None => {}
+            }
+
+            if config.verbose_diagnostics {
+                diagnostic
+                    .message
+                    .push_str(&snippet(self.rope, &diagnostic.range, self.encoding));
+            }
+
+            let Range { start, end } = diagnostic.range;
+            let key = (
+                (start.line, start.character, end.line, end.character),
+                code.to_owned(),
+                diagnostic.message.clone(),
+            );
+            if seen.insert(key) {
+                diagnostics.push(diagnostic);
+            }
+        }
+
+        diagnostics.sort_by_key(|d| (d.range.start, d.severity.map_or(0, severity_rank)));
+        if let Some(distance) = config.coalesce_diagnostics {
+            diagnostics = coalesce(diagnostics, distance, self.uri, self.rope, self.encoding);
+        }
+        diagnostics.truncate(config.max_diagnostics);
+
+        diagnostics
+    }
+}
+
+/// Folds each error into the first error of its run, where an error
+/// starting at most `distance` characters after the end of the previous
+/// one continues the run. The others become related information of the
+/// first. Other severities are kept as they are, with their quick fixes.
+fn coalesce(
+    diagnostics: Vec<Diagnostic>,
+    distance: usize,
+    uri: &Url,
+    rope: &Rope,
+    encoding: Encoding,
+) -> Vec<Diagnostic> {
+    let char_at = |position| {
+        position_to_offset(position, rope, encoding).map(|offset| rope.byte_to_char(offset))
+    };
+
+    let mut coalesced: Vec<Diagnostic> = vec![];
+    // Index of the first error of the current run and where the run ends
+    let mut run: Option<(usize, usize)> = None;
+
+    for diagnostic in diagnostics {
+        if diagnostic.severity != Some(DiagnosticSeverity::ERROR) {
+            coalesced.push(diagnostic);
+            continue;
+        }
+
+        let (Some(start), Some(end)) = (
+            char_at(diagnostic.range.start),
+            char_at(diagnostic.range.end),
+        ) else {
+            coalesced.push(diagnostic);
+            continue;
+        };
+
+        match &mut run {
+            Some((first, run_end)) if start.saturating_sub(*run_end) <= distance => {
+                *run_end = (*run_end).max(end);
+                coalesced[*first]
+                    .related_information
+                    .get_or_insert_with(Vec::new)
+                    .push(DiagnosticRelatedInformation {
+                        location: Location::new(uri.clone(), diagnostic.range),
+                        message: diagnostic.message,
+                    });
+            }
+            _ => {
+                run = Some((coalesced.len(), end));
+                coalesced.push(diagnostic);
+            }
+        }
+    }
+
+    coalesced
+}
+
+/// Longest part of a line shown under a message.
+const SNIPPET_WIDTH: usize = 80;
+
+/// The line a diagnostic starts on, with carets under its range.
+fn snippet(rope: &Rope, range: &Range, encoding: Encoding) -> String {
+    let Some(line) = rope.get_line(range.start.line as usize) else {
+        return String::new();
+    };
+    let line = line.to_string();
+    let line = line.trim_end_matches(['\n', '\r']);
+
+    let start = column_to_char(line, range.start.character, encoding);
+    let end = match range.end.line == range.start.line {
+        true => column_to_char(line, range.end.character, encoding),
+        false => line.chars().count(),
+    };
+
+    // Long lines are cut to a window around the start of the range
+    let skip = start.saturating_sub(SNIPPET_WIDTH / 2);
+    let shown = line
+        .chars()
+        .skip(skip)
+        .take(SNIPPET_WIDTH)
+        .collect::<String>();
+    let marker = if skip > 0 { "…" } else { "" };
+
+    let caret_start = start - skip + marker.chars().count();
+    let carets = end
+        .saturating_sub(start)
+        .clamp(1, SNIPPET_WIDTH - (start - skip));
+
+    format!(
+        "\n    {marker}{shown}\n    {}{}",
+        " ".repeat(caret_start),
+        "^".repeat(carets)
+    )
+}
+
+fn severity_rank(severity: DiagnosticSeverity) -> u8 {
+    match severity {
+        DiagnosticSeverity::ERROR => 0,
+        DiagnosticSeverity::WARNING => 1,
+        DiagnosticSeverity::INFORMATION => 2,
+        _ => 3,
+    }
+}
+
+/// Lines silenced by a `# daleth-ignore [codes]` comment on the line above,
+/// with the codes it names. No codes silences everything on the line.
+fn suppressions(source: &str) -> Vec<(usize, Vec<&str>)> {
+    source
+        .lines()
+        .enumerate()
+        .filter_map(|(line, text)| {
+            let rest = text.trim_start().strip_prefix('#')?.trim_start();
+            let codes = rest.strip_prefix("daleth-ignore")?;
+
+            Some((
+                line + 1,
+                codes
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|code| !code.is_empty())
+                    .collect(),
+            ))
+        })
+        .collect()
+}
+
+/// Code of the typographic character hint, whose `data.replacement` holds the
+/// ASCII character to use instead.
+pub const TYPOGRAPHIC: &str = "typographic-character";
+
+fn ascii_equivalent(c: char) -> Option<char> {
+    match c {
+        '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{2033}' => Some('"'),
+        '\u{2013}' | '\u{2014}' | '\u{2212}' => Some('-'),
+        _ => None,
+    }
+}
+
+/// Part of a document the typographic scan is in.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Scan {
+    Syntax,
+    /// A one-line text or a comment, which end with the line.
+    Line,
+    /// An argument, opened by a typographic quote or not.
+    Argument {
+        typographic: bool,
+    },
+    /// A multi-line text.
+    Text,
+}
+
+/// Typographic characters that stand where the lexer expects syntax, such as
+/// curly quotes around an argument or an en dash starting a text tag. The
+/// document is scanned once, skipping text, arguments and comments the way
+/// the lexer does. Each character found is read as its ASCII equivalent, so
+/// a curly quote opening an argument is closed by the next curly quote.
+fn typographic(source: &str) -> Vec<(ByteRange<usize>, char, char)> {
+    let mut found = vec![];
+    let mut chars = source.char_indices().peekable();
+    let mut scan = Scan::Syntax;
+
+    while let Some((i, c)) = chars.next() {
+        let equivalent = ascii_equivalent(c);
+
+        scan = match (scan, equivalent.unwrap_or(c)) {
+            (Scan::Line, '\n') => Scan::Syntax,
+            (Scan::Argument { .. } | Scan::Text, '\\') => {
+                chars.next();
+                scan
+            }
+            (Scan::Argument { typographic }, '"') if equivalent.is_none() || typographic => {
+                if equivalent.is_some() {
+                    found.push((i..i + c.len_utf8(), c, '"'));
+                }
+                Scan::Syntax
+            }
+            (Scan::Argument { .. }, '\n') => Scan::Syntax,
+            (Scan::Text, '}') => Scan::Syntax,
+            (Scan::Line | Scan::Argument { .. } | Scan::Text, _) => scan,
+            (Scan::Syntax, syntax) => {
+                if let Some(expected) = equivalent {
+                    found.push((i..i + c.len_utf8(), c, expected));
+                }
+
+                match syntax {
+                    '#' | ':' | '-' => Scan::Line,
+                    '"' => Scan::Argument {
+                        typographic: equivalent.is_some(),
+                    },
+                    '{' => Scan::Text,
+                    _ => Scan::Syntax,
+                }
+            }
+        };
+    }
+
+    found
+}
+
+fn check_arity(nodes: &[Node], collector: &mut DiagnosticCollector) {
+    for node in nodes {
+        if !node.has_valid_arity() {
+            collector.push(
+                &ARGUMENT_COUNT,
+                node.tag_span.clone(),
+                format!(
+                    "tag `{}` expects {}, found {}",
+                    node.name(),
+                    node.schema.expected_arguments(),
+                    node.arguments.len()
+                ),
+                None,
+            );
+        }
+
+        check_arity(node.children(), collector);
+    }
+}
+
+/// Code of the unused anchor hint, whose `data.removal` holds the range that
+/// deletes the `a` tag.
+pub const UNUSED_ANCHOR: &str = "unused-anchor";
+
+fn unused_anchors(
+    source: &str,
+    nodes: &[Node],
+    referenced: &HashSet<String>,
+    config: &Config,
+    collector: &mut DiagnosticCollector,
+) {
+    for node in nodes {
+        if let ("a", Some(argument)) = (node.name(), node.arguments.first()) {
+            if !referenced.contains(&argument.value) && !config.is_public_anchor(&argument.value) {
+                // Removing a tag alone on its line removes the line with it
+                let line_start = source[..node.span.start].rfind('\n').map_or(0, |i| i + 1);
+                let line_end = source[node.span.end..]
+                    .find('\n')
+                    .map_or(source.len(), |i| node.span.end + i + 1);
+                let removal = match source[line_start..node.span.start].trim().is_empty()
+                    && source[node.span.end..line_end].trim().is_empty()
+                {
+                    true => line_start..line_end,
+                    false => node.span.clone(),
+                };
+
+                collector.push(
+                    &UNUSED_ANCHOR_CHECK,
+                    argument.span.clone(),
+                    format!("anchor `{}` is never linked to", argument.value),
+                    span_to_range(removal, collector.rope, collector.encoding)
+                        .map(|range| json!({ "removal": range })),
+                );
+            }
+        }
+
+        unused_anchors(source, node.children(), referenced, config, collector);
+    }
+}
+
+fn unknown_languages(nodes: &[Node], config: &Config, collector: &mut DiagnosticCollector) {
+    for node in nodes {
+        if let ("code", Some(language)) = (node.name(), node.arguments.first()) {
+            let known = config
+                .known_languages
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(&language.value));
+
+            if !known {
+                collector.push(
+                    &UNKNOWN_LANGUAGE,
+                    language.span.clone(),
+                    format!("unknown code language `{}`", language.value),
+                    None,
+                );
+            }
+        }
+
+        unknown_languages(node.children(), config, collector);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::Position;
+
+    use super::*;
+
+    fn diagnostics(source: &str, config: &Config) -> Vec<Diagnostic> {
+        let uri = Url::parse("file:///test.dlt").unwrap();
+        analyze(
+            &uri,
+            source,
+            &Rope::from_str(source),
+            Encoding::Utf16,
+            config,
+            None,
+        )
+    }
+
+    /// Messages of the diagnostics with `code`.
+    fn messages(source: &str, code: &str) -> Vec<String> {
+        diagnostics(source, &Config::default())
+            .into_iter()
+            .filter(|d| d.code == Some(NumberOrString::String(code.to_owned())))
+            .map(|d| d.message)
+            .collect()
+    }
+
+    #[test]
+    fn arity_reports_too_few_arguments() {
+        assert_eq!(
+            messages("img\n", ARGUMENT_COUNT.code),
+            ["tag `img` expects 1 argument, found 0"]
+        );
+    }
+
+    #[test]
+    fn arity_reports_too_many_arguments() {
+        assert_eq!(
+            messages("img \"a.png\" \"b.png\"\n", ARGUMENT_COUNT.code),
+            ["tag `img` expects 1 argument, found 2"]
+        );
+        assert_eq!(
+            messages(
+                "row \"start\" \"end\" [\n    p: Text\n]\n",
+                ARGUMENT_COUNT.code
+            ),
+            ["tag `row` expects 0 to 1 arguments, found 2"]
+        );
+    }
+
+    #[test]
+    fn arity_accepts_optional_arguments() {
+        assert!(messages("row [\n    p: Text\n]\n", ARGUMENT_COUNT.code).is_empty());
+        assert!(messages("row \"center\" [\n    p: Text\n]\n", ARGUMENT_COUNT.code).is_empty());
+    }
+
+    #[test]
+    fn arity_checks_nested_tags() {
+        assert_eq!(
+            messages("row [\n    img\n]\n", ARGUMENT_COUNT.code),
+            ["tag `img` expects 1 argument, found 0"]
+        );
+    }
+
+    #[test]
+    fn typographic_hints_count_columns_in_utf16() {
+        let hints = diagnostics("p: Grüße\nimg \u{201C}ñ.png\u{201D}\n", &Config::default())
+            .into_iter()
+            .filter(|d| d.code == Some(NumberOrString::String(TYPOGRAPHIC.to_owned())))
+            .map(|d| (d.range.start, d.range.end))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            hints,
+            [
+                (Position::new(1, 4), Position::new(1, 5)),
+                (Position::new(1, 10), Position::new(1, 11)),
+            ]
+        );
+    }
+
+    fn typographic_characters(source: &str) -> Vec<(usize, char)> {
+        typographic(source)
+            .into_iter()
+            .map(|(span, found, _)| (span.start, found))
+            .collect()
+    }
+
+    #[test]
+    fn typographic_scan_skips_text_arguments_and_comments() {
+        assert_eq!(
+            typographic_characters(
+                "p: \u{201C}a\u{201D}\n# \u{2013}\nimg \"\u{201C}\"\ncode \"c\" {\n\u{2013}\n}\n\u{2013} Item \u{201C}\n"
+            ),
+            [(44, '\u{2013}')]
+        );
+        assert_eq!(
+            typographic_characters("row [\n    img \u{201C}a \\\u{201D}\u{201D}\n]\n"),
+            [(14, '\u{201C}'), (23, '\u{201D}')]
+        );
+    }
+
+    #[test]
+    fn typographic_scan_finds_every_character() {
+        let source = "img \u{201C}a.png\u{201D}\n".repeat(200);
+        assert_eq!(typographic(&source).len(), 400);
+    }
+
+    #[test]
+    fn documents_that_lex_get_no_typographic_hints() {
+        let hints = diagnostics(
+            "p: \u{201C}Quoted\u{201D} \u{2013} text\n",
+            &Config::default(),
+        )
+        .into_iter()
+        .filter(|d| d.code == Some(NumberOrString::String(TYPOGRAPHIC.to_owned())))
+        .count();
+        assert_eq!(hints, 0);
+    }
+
+    fn argument_counts(source: &str, config: &Config) -> Vec<(u32, Option<DiagnosticSeverity>)> {
+        diagnostics(source, config)
+            .into_iter()
+            .filter(|d| d.code == Some(NumberOrString::String(ARGUMENT_COUNT.code.to_owned())))
+            .map(|d| (d.range.start.line, d.severity))
+            .collect()
+    }
+
+    #[test]
+    fn ignore_comments_suppress_the_next_line() {
+        let error = Some(DiagnosticSeverity::ERROR);
+
+        assert_eq!(
+            argument_counts("# daleth-ignore\nimg\nimg\n", &Config::default()),
+            [(2, error)]
+        );
+        assert_eq!(
+            argument_counts("# daleth-ignore argument-count\nimg\n", &Config::default()),
+            []
+        );
+        assert_eq!(
+            argument_counts("# daleth-ignore parse-error\nimg\n", &Config::default()),
+            [(1, error)]
+        );
+    }
+
+    #[test]
+    fn severity_overrides_change_or_drop_diagnostics() {
+        let mut config = Config::default();
+        config
+            .diagnostic_severity
+            .insert(ARGUMENT_COUNT.code.to_owned(), SeverityOverride::Warning);
+        assert_eq!(
+            argument_counts("img\n", &config),
+            [(0, Some(DiagnosticSeverity::WARNING))]
+        );
+
+        config
+            .diagnostic_severity
+            .insert(ARGUMENT_COUNT.code.to_owned(), SeverityOverride::Off);
+        assert_eq!(argument_counts("img\n", &config), []);
+    }
+
+    #[test]
+    fn finish_dedupes_sorts_and_caps() {
+        let uri = Url::parse("file:///test.dlt").unwrap();
+        let source = "h1: One\nh1: Two\nh1: Three\n";
+        let rope = Rope::from_str(source);
+
+        let mut collector = DiagnosticCollector::new(&uri, source, &rope, Encoding::Utf16);
+        collector.push(&TYPOGRAPHIC_CHARACTER, 8..10, "second".to_owned(), None);
+        collector.push(&LEX_ERROR, 16..18, "third".to_owned(), None);
+        collector.push(&TYPOGRAPHIC_CHARACTER, 0..2, "first hint".to_owned(), None);
+        collector.push(&TYPOGRAPHIC_CHARACTER, 8..10, "second".to_owned(), None);
+        collector.push(&LEX_ERROR, 0..2, "first error".to_owned(), None);
+
+        let messages = |diagnostics: Vec<Diagnostic>| {
+            diagnostics
+                .into_iter()
+                .map(|d| d.message)
+                .collect::<Vec<_>>()
+        };
+        let config = Config {
+            max_diagnostics: 3,
+            ..Config::default()
+        };
+
+        assert_eq!(
+            messages(collector.finish(&config)),
+            ["first error", "first hint", "second"]
+        );
+    }
+
+    #[test]
+    fn coalesce_merges_close_errors_before_the_cap() {
+        let uri = Url::parse("file:///test.dlt").unwrap();
+        let source = "h1: One\nh1: Two\nh1: Three\nh1: Four\n";
+        let rope = Rope::from_str(source);
+
+        let mut collector = DiagnosticCollector::new(&uri, source, &rope, Encoding::Utf16);
+        collector.push(&LEX_ERROR, 0..2, "first".to_owned(), None);
+        collector.push(&LEX_ERROR, 4..6, "close".to_owned(), None);
+        collector.push(&TYPOGRAPHIC_CHARACTER, 5..6, "hint".to_owned(), None);
+        collector.push(&LEX_ERROR, 9..11, "apart".to_owned(), None);
+        collector.push(&LEX_ERROR, 27..29, "last".to_owned(), None);
+
+        let config = Config {
+            coalesce_diagnostics: Some(2),
+            max_diagnostics: 3,
+            ..Config::default()
+        };
+        let diagnostics = collector.finish(&config);
+
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|d| d.message.as_str())
+                .collect::<Vec<_>>(),
+            ["first", "hint", "apart"]
+        );
+        assert_eq!(
+            diagnostics[0].related_information,
+            Some(vec![DiagnosticRelatedInformation {
+                location: Location::new(uri, line_range(4, 6)),
+                message: "close".to_owned(),
+            }])
+        );
+        assert_eq!(diagnostics[1].related_information, None);
+        assert_eq!(diagnostics[2].related_information, None);
+    }
+
+    fn line_range(start: u32, end: u32) -> Range {
+        Range::new(Position::new(0, start), Position::new(0, end))
+    }
+
+    #[test]
+    fn snippet_puts_carets_under_the_range() {
+        let rope = Rope::from_str("p: Grüße img\n");
+
+        assert_eq!(
+            snippet(&rope, &line_range(9, 12), Encoding::Utf16),
+            "\n    p: Grüße img\n             ^^^"
+        );
+        assert_eq!(
+            snippet(&rope, &line_range(3, 3), Encoding::Utf16),
+            "\n    p: Grüße img\n       ^"
+        );
+    }
+
+    #[test]
+    fn snippet_cuts_long_lines_around_the_start() {
+        let line = format!("{}img{}", "a".repeat(100), "b".repeat(100));
+        let rope = Rope::from_str(&line);
+
+        let snippet = snippet(&rope, &line_range(100, 103), Encoding::Utf16);
+        let [_, shown, carets] = snippet.split('\n').collect::<Vec<_>>()[..] else {
+            panic!("unexpected snippet {snippet:?}");
+        };
+
+        let expected = format!("…{}img{}", "a".repeat(40), "b".repeat(37));
+        assert_eq!(shown, format!("    {expected}"));
+        assert_eq!(shown.chars().count(), 4 + 1 + SNIPPET_WIDTH);
+        assert_eq!(carets, format!("    {}^^^", " ".repeat(41)));
+    }
+
+    /// Names and removal ranges of the unused anchor hints of `source`, where
+    /// only `kept` is linked to.
+    fn unused(source: &str, public_anchors: &[&str]) -> Vec<(String, Value)> {
+        let uri = Url::parse("file:///test.dlt").unwrap();
+        let config = Config {
+            public_anchors: public_anchors.iter().map(|&p| p.to_owned()).collect(),
+            ..Config::default()
+        };
+        let referenced = HashSet::from(["kept".to_owned()]);
+
+        analyze(
+            &uri,
+            source,
+            &Rope::from_str(source),
+            Encoding::Utf16,
+            &config,
+            Some(&referenced),
+        )
+        .into_iter()
+        .filter(|d| d.code == Some(NumberOrString::String(UNUSED_ANCHOR.to_owned())))
+        .map(|d| (d.message, d.data.unwrap()["removal"].clone()))
+        .collect()
+    }
+
+    fn removal(start: (u32, u32), end: (u32, u32)) -> Value {
+        json!(Range::new(
+            Position::new(start.0, start.1),
+            Position::new(end.0, end.1)
+        ))
+    }
+
+    #[test]
+    fn public_anchors_are_never_unused() {
+        let source = "a \"intro-1\"\na \"intro\"\na \"kept\"\na \"other\"\n";
+
+        assert_eq!(
+            unused(source, &["intro-*"]),
+            [
+                (
+                    "anchor `intro` is never linked to".to_owned(),
+                    removal((1, 0), (2, 0))
+                ),
+                (
+                    "anchor `other` is never linked to".to_owned(),
+                    removal((3, 0), (4, 0))
+                ),
+            ]
+        );
+        assert_eq!(
+            unused(source, &["intro", "other"]),
+            [(
+                "anchor `intro-1` is never linked to".to_owned(),
+                removal((0, 0), (1, 0))
+            )]
+        );
+    }
+
+    #[test]
+    fn unused_anchor_removal_keeps_the_rest_of_the_line() {
+        assert_eq!(
+            unused("row [\n    a \"other\" # why\n]\n", &[]),
+            [(
+                "anchor `other` is never linked to".to_owned(),
+                removal((1, 4), (1, 13))
+            )]
+        );
+    }
+
+    /// Messages and ranges of the unknown-language hints of `source`.
+    fn unknown_languages_of(source: &str, known: &[&str]) -> Vec<(String, Range)> {
+        let config = Config {
+            known_languages: known.iter().map(|language| language.to_string()).collect(),
+            ..Config::default()
+        };
+
+        diagnostics(source, &config)
+            .into_iter()
+            .filter(|d| d.code == Some(NumberOrString::String(UNKNOWN_LANGUAGE.code.to_owned())))
+            .map(|d| (d.message, d.range))
+            .collect()
+    }
+
+    #[test]
+    fn unknown_languages_are_hinted() {
+        let source = "code \"Python\" {\npass\n}\nrow [\n    code \"cobol\" {\nSTOP RUN.\n}\n]\n";
+
+        assert_eq!(
+            unknown_languages_of(source, &["python", "rust"]),
+            [(
+                "unknown code language `cobol`".to_owned(),
+                Range::new(Position::new(4, 9), Position::new(4, 16))
+            )]
+        );
+        assert_eq!(unknown_languages_of(source, &["PYTHON", "Cobol"]), []);
+        assert_eq!(unknown_languages_of(source, &[]), []);
+    }
+}
@@ -0,0 +1,41 @@
+use std::borrow::Cow;
+
+use serde_json::json;
+use tower_lsp::jsonrpc::{Error, ErrorCode};
+use tower_lsp::lsp_types::Url;
+
+/// Code of the error returned for a request on a document the server has not
+/// been sent with `textDocument/didOpen`. Its `data` is
+/// `{ "reason": "documentNotOpen", "uri": uri }`, so a client can open the
+/// document and retry.
+pub const DOCUMENT_NOT_OPEN: i64 = -32001;
+
+/// Failures of requests, mapped to JSON-RPC errors.
+#[derive(Debug)]
+pub enum ServerError {
+    DocumentNotOpen(Url),
+    Lexer,
+    AnalysisOnly,
+}
+
+impl From<ServerError> for Error {
+    fn from(error: ServerError) -> Self {
+        match error {
+            ServerError::DocumentNotOpen(uri) => Error {
+                code: ErrorCode::ServerError(DOCUMENT_NOT_OPEN),
+                message: Cow::Borrowed("Document is not open"),
+                data: Some(json!({ "reason": "documentNotOpen", "uri": uri })),
+            },
+            ServerError::Lexer => Error {
+                code: ErrorCode::InternalError,
+                message: Cow::Borrowed("Lexer error"),
+                data: None,
+            },
+            ServerError::AnalysisOnly => Error {
+                code: ErrorCode::InvalidRequest,
+                message: Cow::Borrowed("Edits are disabled in analysis-only mode"),
+                data: None,
+            },
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use dalet::daleth::lexer::types::Token;
+use dalet::daleth::types::Spanned;
+use ropey::Rope;
+use tower_lsp::lsp_types::{FoldingRange, FoldingRangeKind};
+
+use crate::tokens::trim_span;
+use crate::tree::{Body, Node};
+
+/// Tags spanning several lines, and runs of comments on consecutive lines.
+/// Daleth only has line comments, so a run is the only comment block.
+pub fn folding_ranges(
+    source: &str,
+    tokens: &[Spanned<Token>],
+    nodes: &[Node],
+    rope: &Rope,
+) -> Vec<FoldingRange> {
+    let mut ranges = vec![];
+    tag_folds(nodes, rope, &mut ranges);
+    comment_folds(source, tokens, rope, &mut ranges);
+
+    ranges.sort_by_key(|range| (range.start_line, range.end_line));
+    ranges
+}
+
+fn tag_folds(nodes: &[Node], rope: &Rope, ranges: &mut Vec<FoldingRange>) {
+    for node in nodes {
+        let line = |offset: usize| rope.try_byte_to_line(offset).ok();
+
+        // A closing bracket on a line of its own stays visible
+        let end = match &node.body {
+            Body::Tags {
+                close: Some(close), ..
+            } => line(close.start).map(|l| l.saturating_sub(1)),
+            _ => line(node.span.end),
+        };
+
+        if let (Some(start), Some(end)) = (line(node.span.start), end) {
+            if end > start {
+                ranges.push(fold(start, end, None));
+            }
+        }
+
+        tag_folds(node.children(), rope, ranges);
+    }
+}
+
+fn comment_folds(
+    source: &str,
+    tokens: &[Spanned<Token>],
+    rope: &Rope,
+    ranges: &mut Vec<FoldingRange>,
+) {
+    let mut run: Option<(usize, usize)> = None;
+
+    for (token, span) in tokens {
+        let Token::Comment(_) = token else {
+            continue;
+        };
+
+        let span = trim_span(source, *span);
+        let Ok(line) = rope.try_byte_to_line(span.start) else {
+            continue;
+        };
+
+        // Only comments with nothing before them on their line form a block
+        let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        if !source[line_start..span.start].trim().is_empty() {
+            continue;
+        }
+
+        run = match run {
+            Some((start, end)) if end + 1 == line => Some((start, line)),
+            Some((start, end)) => {
+                if end > start {
+                    ranges.push(fold(start, end, Some(FoldingRangeKind::Comment)));
+                }
+                Some((line, line))
+            }
+            None => Some((line, line)),
+        };
+    }
+
+    if let Some((start, end)) = run {
+        if end > start {
+            ranges.push(fold(start, end, Some(FoldingRangeKind::Comment)));
+        }
+    }
+}
+
+fn fold(start: usize, end: usize, kind: Option<FoldingRangeKind>) -> FoldingRange {
+    FoldingRange {
+        start_line: start as u32,
+        end_line: end as u32,
+        kind,
+        ..FoldingRange::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chumsky::Parser;
+    use dalet::daleth::lexer::full_lexer;
+
+    use super::*;
+    use crate::tree::Tree;
+
+    /// Start line, end line and whether each fold is a comment.
+    fn folds(source: &str) -> Vec<(u32, u32, bool)> {
+        let tokens = full_lexer().parse(source).into_result().unwrap();
+        let tree = Tree::new(source, &tokens);
+
+        folding_ranges(source, &tokens, &tree.nodes, &Rope::from_str(source))
+            .into_iter()
+            .map(|range| {
+                let comment = range.kind == Some(FoldingRangeKind::Comment);
+                (range.start_line, range.end_line, comment)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn comment_runs_fold_together() {
+        assert_eq!(
+            folds("# one\n# two\n# three\n\n# four\n# five\nh1: Notes\n# six\n"),
+            [(0, 2, true), (4, 5, true)]
+        );
+    }
+
+    #[test]
+    fn closing_brackets_stay_visible() {
+        assert_eq!(
+            folds("row [\n    ul [\n        - Apples\n    ]\n]\n"),
+            [(0, 3, false), (1, 2, false)]
+        );
+    }
+
+    #[test]
+    fn comments_after_code_are_not_folded() {
+        let source = "br # one\n# two\nbr\n";
+        let tokens = full_lexer().parse(source).into_result().unwrap();
+        let comments = tokens
+            .iter()
+            .filter(|(token, _)| matches!(token, Token::Comment(_)))
+            .count();
+
+        assert_eq!(comments, 2);
+        assert_eq!(folds(source), []);
+    }
+}
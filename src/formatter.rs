@@ -0,0 +1,562 @@
+use std::ops::Range as ByteRange;
+
+use chumsky::Parser;
+use dalet::daleth::custom_parsers::table_to_string;
+use dalet::daleth::lexer::full_lexer;
+use dalet::daleth::lexer::types::Token;
+use dalet::daleth::types::Spanned;
+
+use crate::ast::{differences, parse};
+use crate::tokens::{tag_name, trim_span};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatOptions {
+    pub indent: String,
+    pub keep_empty_lines: bool,
+    pub separate_top_level: bool,
+    /// Pads the leading `meta` tags so their values line up.
+    pub align_meta: bool,
+    /// Sorts the leading `meta` tags by key.
+    pub sort_meta: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            indent: "    ".to_owned(),
+            keep_empty_lines: true,
+            separate_top_level: false,
+            align_meta: true,
+            sort_meta: false,
+        }
+    }
+}
+
+impl FormatOptions {
+    pub fn compact() -> Self {
+        Self {
+            indent: "  ".to_owned(),
+            keep_empty_lines: false,
+            align_meta: false,
+            ..Self::default()
+        }
+    }
+
+    pub fn expanded() -> Self {
+        Self {
+            separate_top_level: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn aligned() -> Self {
+        Self {
+            align_meta: true,
+            sort_meta: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn styles() -> Vec<(&'static str, Self)> {
+        vec![
+            ("default", Self::default()),
+            ("compact", Self::compact()),
+            ("expanded", Self::expanded()),
+            ("aligned", Self::aligned()),
+        ]
+    }
+}
+
+pub fn format(spanned_tokens: &[Spanned<Token>], options: &FormatOptions) -> String {
+    let mut formatter = Formatter {
+        options,
+        current_indent: 0,
+    };
+
+    formatter.format(spanned_tokens)
+}
+
+/// Formats `source` with `format` and lists every semantic difference between
+/// the original and the formatted document.
+pub fn verify<F>(source: &str, format: F) -> Result<Vec<String>, &'static str>
+where
+    F: Fn(&[Spanned<Token>]) -> String,
+{
+    let original = parse(source).ok_or("Document has syntax errors")?;
+    let tokens = full_lexer()
+        .parse(source)
+        .into_result()
+        .map_err(|_| "Lexer error")?;
+
+    let formatted = format(&tokens);
+
+    match parse(&formatted) {
+        Some(formatted) => Ok(differences(&original, &formatted)),
+        None => Ok(vec!["Formatted document has syntax errors".to_owned()]),
+    }
+}
+
+struct Formatter<'a> {
+    options: &'a FormatOptions,
+    current_indent: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn format(&mut self, spanned_tokens: &[Spanned<Token>]) -> String {
+        let (mut entries, rest) = front_matter(spanned_tokens);
+        if entries.is_empty() {
+            return self.format_tokens(spanned_tokens);
+        }
+
+        if self.options.sort_meta {
+            entries.sort_by_key(|(key, _)| *key);
+        }
+
+        let width = match self.options.align_meta {
+            true => entries.iter().map(|(key, _)| key.chars().count()).max(),
+            false => None,
+        };
+
+        let mut formatted = String::new();
+        for (key, value) in entries {
+            let padding = width.map_or(0, |width| width - key.chars().count());
+            formatted.push_str(&format!("meta \"{key}\":{} {value}\n", " ".repeat(padding)));
+        }
+
+        if rest.is_empty() {
+            return formatted;
+        }
+
+        let blank_line = self.options.separate_top_level
+            || self.options.keep_empty_lines
+                && rest.first().is_some_and(|(t, _)| *t == Token::EmptyLine);
+        if blank_line {
+            formatted.push('\n');
+        }
+
+        formatted.push_str(&self.format_tokens(rest));
+        formatted
+    }
+
+    fn format_tokens(&mut self, spanned_tokens: &[Spanned<Token>]) -> String {
+        let tokens: Vec<&Token> = spanned_tokens
+            .iter()
+            .map(|(token, _)| token)
+            .filter(|token| self.options.keep_empty_lines || **token != Token::EmptyLine)
+            .collect();
+
+        let mut formatted = String::new();
+
+        for i in 0..tokens.len() {
+            let last2 = i.checked_sub(2).map(|i| tokens[i]);
+            let last1 = i.checked_sub(1).map(|i| tokens[i]);
+            let current_token = tokens[i];
+
+            formatted.push_str(additional_str(last2, last1, current_token));
+
+            if self.options.separate_top_level
+                && self.current_indent == 0
+                && starts_tag(current_token)
+                && last1.is_some_and(|t| !matches!(t, Token::EmptyLine | Token::Comment(_)))
+            {
+                while !formatted.ends_with("\n\n") {
+                    formatted.push('\n');
+                }
+            }
+
+            let to_push = match code_body(last2, last1, current_token) {
+                Some(body) => body,
+                None => self.token(current_token),
+            };
+            formatted.push_str(&to_push);
+        }
+
+        let mut formatted = formatted.trim().to_owned();
+        formatted.push('\n');
+
+        formatted
+    }
+
+    fn token(&mut self, token: &Token) -> String {
+        match token {
+            Token::LSquare => {
+                self.current_indent += 1;
+                " [\n".to_owned()
+            }
+            Token::RSquare => {
+                self.current_indent = self.current_indent.saturating_sub(1);
+                format!("{}\n", self.prepend_indent("]", self.current_indent))
+            }
+
+            Token::NumberArgument(n) => format!("{n}"),
+            Token::TextArgument(t) => format!(" \"{t}\""),
+            Token::TextBody(t) => format!(": {}\n", t),
+            Token::MLText(t) => format!(
+                " {{\n{}\n{}\n",
+                self.set_indent(t, self.current_indent + 1),
+                self.prepend_indent("}", self.current_indent)
+            ),
+            Token::MLMSText(n, t) => format!(
+                " {{~{n}\n{}\n{}\n",
+                self.set_indent(t, self.current_indent + 1),
+                self.prepend_indent("}", self.current_indent)
+            ),
+            Token::MLRText(t) => format!(" {{#{t}}}\n"),
+            Token::Comment(c) => format!(
+                "{}\n",
+                self.prepend_indent(&format!("#{c}"), self.current_indent)
+            ),
+
+            Token::TextTag(t) => format!(
+                "{}\n",
+                self.prepend_indent(&format!("- {}", t), self.current_indent)
+            ),
+
+            Token::ElOpen => {
+                let s = self.prepend_indent("[[", self.current_indent);
+                self.current_indent += 1;
+                format!("{s}\n")
+            }
+            Token::ElClose => {
+                self.current_indent = self.current_indent.saturating_sub(1);
+                format!("{}\n", self.prepend_indent("]]", self.current_indent))
+            }
+            Token::Paragraph(t) => format!(
+                "{}\n{}\n{}\n",
+                self.prepend_indent("{-", self.current_indent),
+                self.set_indent(t, self.current_indent + 1),
+                self.prepend_indent("}", self.current_indent)
+            ),
+            Token::TableSyntax(rows) => format!(
+                "{}\n{}\n{}\n",
+                self.prepend_indent("{> table", self.current_indent),
+                self.set_indent(&table_to_string(rows), self.current_indent + 1),
+                self.prepend_indent("}", self.current_indent)
+            ),
+
+            Token::EmptyLine => "\n".to_owned(),
+
+            tag => self.prepend_indent(tag_name(tag).unwrap_or_default(), self.current_indent),
+        }
+    }
+
+    fn set_indent(&self, input: &str, indent: usize) -> String {
+        self.prepend_indent(&trim_indent(input), indent)
+    }
+
+    fn prepend_indent(&self, input: &str, indent: usize) -> String {
+        let indent = self.options.indent.repeat(indent);
+
+        // Blank lines of multi-line text get no indentation, so that
+        // formatting does not add trailing whitespace. Lines holding only
+        // whitespace keep what they have.
+        input
+            .lines()
+            .map(|line| match line.trim().is_empty() {
+                true => line.to_owned(),
+                false => format!("{}{}", indent, line),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// The `meta "key": value` tags at the very start of the document, and the
+/// tokens after them.
+fn front_matter<'a, 'src>(
+    tokens: &'a [Spanned<Token<'src>>],
+) -> (Vec<(&'src str, &'src str)>, &'a [Spanned<Token<'src>>]) {
+    let mut entries = vec![];
+    let mut rest = tokens;
+
+    while let [(Token::Meta, _), (Token::TextArgument(key), _), (Token::TextBody(value), _), tail @ ..] =
+        rest
+    {
+        entries.push((*key, *value));
+        rest = tail;
+    }
+
+    (entries, rest)
+}
+
+fn starts_tag(token: &Token) -> bool {
+    !matches!(
+        token,
+        Token::LSquare
+            | Token::RSquare
+            | Token::ElClose
+            | Token::NumberArgument(_)
+            | Token::TextArgument(_)
+            | Token::TextBody(_)
+            | Token::MLText(_)
+            | Token::MLMSText(_, _)
+            | Token::MLRText(_)
+            | Token::EmptyLine
+    )
+}
+
+/// The multi-line body of a `code` tag exactly as written, since any change to
+/// its indentation or blank lines would change the code.
+fn code_body(last2: Option<&Token>, last1: Option<&Token>, current: &Token) -> Option<String> {
+    let code = matches!(
+        (last2, last1),
+        (_, Some(Token::Code)) | (Some(Token::Code), Some(Token::TextArgument(_)))
+    );
+
+    match current {
+        Token::MLText(t) if code => Some(format!(" {{{t}}}\n")),
+        Token::MLMSText(n, t) if code => Some(format!(" {{~{n}{t}}}\n")),
+        _ => None,
+    }
+}
+
+/// Spans of the multi-line `code` bodies, which the formatter keeps as written.
+pub fn code_bodies(source: &str, tokens: &[Spanned<Token>]) -> Vec<ByteRange<usize>> {
+    (0..tokens.len())
+        .filter(|&i| {
+            let last2 = i.checked_sub(2).map(|i| &tokens[i].0);
+            let last1 = i.checked_sub(1).map(|i| &tokens[i].0);
+            code_body(last2, last1, &tokens[i].0).is_some()
+        })
+        .map(|i| trim_span(source, tokens[i].1))
+        .collect()
+}
+
+fn additional_str<'src>(
+    last2: Option<&Token<'src>>,
+    last1: Option<&Token<'src>>,
+    current: &Token<'src>,
+) -> &'src str {
+    if let Some(last1) = last1 {
+        // No body, no arg
+        if [Token::Br, Token::Hr].contains(last1) {
+            return "\n";
+        }
+
+        if let Token::NumberArgument(_) = current {
+            if [Token::H, Token::A].contains(last1) {
+                return "";
+            } else {
+                return " ";
+            }
+        }
+
+        if let Some(last2) = last2 {
+            // No body, with arg
+            if [Token::Img, Token::Footlnk, Token::A].contains(last2) {
+                return "\n";
+            }
+
+            // Optional body
+            if [Token::Link, Token::Navlink, Token::Btn, Token::Navbtn].contains(last2) {
+                return match current {
+                    Token::LSquare
+                    | Token::TextBody(_)
+                    | Token::MLText(_)
+                    | Token::MLMSText(_, _)
+                    | Token::MLRText(_) => "",
+
+                    _ => "\n",
+                };
+            }
+        }
+    }
+
+    ""
+}
+
+fn trim_indent(input: &str) -> String {
+    let lines: Vec<&str> = trim_unused(input).lines().collect();
+
+    let min_indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.chars().take_while(|c| c.is_whitespace()).count())
+        .min()
+        .unwrap_or(0);
+
+    lines
+        .into_iter()
+        .map(|line| line.get(min_indent..).unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn trim_unused(s: &str) -> &str {
+    let mut trim_start = 0;
+    let mut been_newlines = false;
+
+    for start_char in s.chars() {
+        if !been_newlines && start_char.is_whitespace() && start_char != '\n' && start_char != '\r'
+        {
+            trim_start += start_char.len_utf8();
+        } else if start_char != '\n' && start_char != '\r' {
+            break;
+        } else {
+            been_newlines = true;
+            trim_start += 1;
+        }
+    }
+
+    s[trim_start..].trim_end()
+}
+
+#[cfg(test)]
+mod tests {
+    use chumsky::Parser;
+    use dalet::daleth::lexer::full_lexer;
+
+    use super::*;
+
+    const SOURCE: &str = "h1: Notes\nrow [\n    p: Text\n]\n";
+
+    const FRONT_MATTER: &str = "meta \"title\": Notes\nmeta \"by\": Someone\n\nh1: Notes\n";
+
+    fn formatted(source: &str, options: &FormatOptions) -> String {
+        let tokens = full_lexer().parse(source).into_result().unwrap();
+        format(&tokens, options)
+    }
+
+    #[test]
+    fn verify_accepts_the_formatter() {
+        let differences = verify(SOURCE, |tokens| format(tokens, &FormatOptions::default()));
+        assert_eq!(differences, Ok(vec![]));
+    }
+
+    #[test]
+    fn verify_reports_a_changed_tree() {
+        let differences = verify(SOURCE, |_| "h1: Notes\nrow [\n    p: Other\n]\n".to_owned());
+        assert_eq!(
+            differences,
+            Ok(vec![
+                "/1/0: `p` text changed from \"Text\" to \"Other\"".to_owned()
+            ])
+        );
+    }
+
+    #[test]
+    fn verify_reports_unparsable_output() {
+        let differences = verify(SOURCE, |_| "row [\n".to_owned());
+        assert_eq!(
+            differences,
+            Ok(vec!["Formatted document has syntax errors".to_owned()])
+        );
+    }
+
+    #[test]
+    fn verify_rejects_a_broken_source() {
+        assert!(verify("h1 Notes", |_| String::new()).is_err());
+    }
+
+    #[test]
+    fn front_matter_values_are_aligned() {
+        assert_eq!(
+            formatted(FRONT_MATTER, &FormatOptions::default()),
+            "meta \"title\": Notes\nmeta \"by\":    Someone\n\nh1: Notes\n"
+        );
+    }
+
+    #[test]
+    fn front_matter_is_sorted() {
+        let options = FormatOptions {
+            align_meta: false,
+            sort_meta: true,
+            ..FormatOptions::default()
+        };
+
+        assert_eq!(
+            formatted(FRONT_MATTER, &options),
+            "meta \"by\": Someone\nmeta \"title\": Notes\n\nh1: Notes\n"
+        );
+    }
+
+    #[test]
+    fn meta_after_other_tags_is_left_in_place() {
+        let source = "h1: Notes\nmeta \"title\": Notes\nmeta \"by\": Someone\n";
+        let options = FormatOptions {
+            sort_meta: true,
+            ..FormatOptions::default()
+        };
+
+        assert_eq!(formatted(source, &options), source);
+    }
+
+    #[test]
+    fn verify_compares_code_exactly() {
+        let source = "code \"python\" {\n    if x:\n        pass\n}\n";
+        let differences = verify(source, |_| {
+            "code \"python\" {\n    if x:\n    pass\n}\n".to_owned()
+        });
+
+        assert_eq!(
+            differences,
+            Ok(vec![
+                "/0: `code` text changed from \"if x:\\n    pass\" to \"if x:\\npass\"".to_owned()
+            ])
+        );
+    }
+
+    #[test]
+    fn verify_collapses_whitespace_in_other_text() {
+        let source = "p {\n    Two  words\n}\n";
+
+        assert_eq!(
+            verify(source, |_| "p {\n    Two words\n}\n".to_owned()),
+            Ok(vec![])
+        );
+        assert_eq!(
+            verify(source, |_| "p {\n    Two other\n}\n".to_owned()),
+            Ok(vec![
+                "/0: `p` text changed from \"Two  words\" to \"Two other\"".to_owned()
+            ])
+        );
+    }
+
+    #[test]
+    fn code_bodies_keep_their_bytes() {
+        let body = "\tfn main() {  \n\n  \\}\n";
+        let source = format!(
+            "row [\n  code \"rust\" {{\n{body}}}\n  code \"c\" {{~2\n      int x;\n  }}\n]\n"
+        );
+
+        assert_eq!(
+            formatted(&source, &FormatOptions::default()),
+            format!(
+                "row [\n    code \"rust\" {{\n{body}}}\n    code \"c\" {{~2\n      int x;\n  }}\n]\n"
+            )
+        );
+    }
+
+    #[test]
+    fn multi_line_text_keeps_its_relative_indentation() {
+        assert_eq!(
+            formatted(
+                "row [\n  p {\n      first\n        nested\n      last\n  }\n]\n",
+                &FormatOptions::default()
+            ),
+            "row [\n    p {\n        first\n          nested\n        last\n    }\n]\n"
+        );
+    }
+
+    #[test]
+    fn blank_lines_of_multi_line_text_get_no_indentation() {
+        assert_eq!(
+            formatted(
+                "row [\nrow [\np {\nfirst\n\n  \n    nested\n}\n]\n]\n",
+                &FormatOptions::default()
+            ),
+            "row [\n    row [\n        p {\n            first\n\n  \n                nested\n        }\n    ]\n]\n"
+        );
+    }
+
+    #[test]
+    fn structural_indentation_is_normalized() {
+        assert_eq!(
+            formatted(
+                "row [\n        p: Text\n  ul [\n- Apples\n          ]\n]\n",
+                &FormatOptions::default()
+            ),
+            "row [\n    p: Text\n    ul [\n        - Apples\n    ]\n]\n"
+        );
+    }
+}
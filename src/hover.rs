@@ -0,0 +1,152 @@
+use tower_lsp::lsp_types::{MarkupContent, MarkupKind};
+
+use crate::schema::TagSchema;
+use crate::tokens::char_name;
+
+enum Block {
+    Code(String),
+    Text(String),
+    List(Vec<(String, String)>),
+}
+
+pub struct HoverBuilder {
+    kind: MarkupKind,
+    blocks: Vec<Block>,
+}
+
+impl HoverBuilder {
+    pub fn new(kind: MarkupKind) -> Self {
+        Self {
+            kind,
+            blocks: vec![],
+        }
+    }
+
+    pub fn code(mut self, code: impl Into<String>) -> Self {
+        self.blocks.push(Block::Code(code.into()));
+        self
+    }
+
+    pub fn text(mut self, text: impl Into<String>) -> Self {
+        self.blocks.push(Block::Text(text.into()));
+        self
+    }
+
+    pub fn list(mut self, items: Vec<(String, String)>) -> Self {
+        if !items.is_empty() {
+            self.blocks.push(Block::List(items));
+        }
+        self
+    }
+
+    pub fn tag(self, schema: &TagSchema) -> Self {
+        let arguments = schema
+            .arguments
+            .iter()
+            .map(|argument| (argument.name.to_owned(), argument.description.to_owned()))
+            .collect();
+
+        self.code(schema.signature())
+            .text(schema.description)
+            .list(arguments)
+    }
+
+    pub fn escape(self, literal: &str, c: char) -> Self {
+        self.text(format!("Escape sequence `{literal}`"))
+            .text(format!("`{c}` {} (U+{:04X})", char_name(c), c as u32))
+    }
+
+    pub fn reading_time(self, words: usize, words_per_minute: f64) -> Self {
+        if words_per_minute <= 0.0 {
+            return self.text(format!("{words} words"));
+        }
+
+        let minutes = (words as f64 / words_per_minute).ceil().max(1.0);
+        self.text(format!("{words} words, about {minutes} min read"))
+    }
+
+    pub fn build(self) -> MarkupContent {
+        let markdown = self.kind == MarkupKind::Markdown;
+
+        let value = self
+            .blocks
+            .into_iter()
+            .map(|block| match block {
+                Block::Code(code) if markdown => format!("```daleth\n{code}\n```"),
+                Block::Code(code) => code,
+                Block::Text(text) if markdown => text,
+                Block::Text(text) => strip_markdown(&text),
+                Block::List(items) => items
+                    .into_iter()
+                    .map(|(name, description)| {
+                        if markdown {
+                            format!("- `{name}`: {description}")
+                        } else {
+                            format!("- {name}: {}", strip_markdown(&description))
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        MarkupContent {
+            kind: self.kind,
+            value,
+        }
+    }
+}
+
+fn strip_markdown(text: &str) -> String {
+    text.replace(['`', '*'], "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(kind: MarkupKind) -> String {
+        HoverBuilder::new(kind)
+            .code("h level: body")
+            .text("A `heading`")
+            .list(vec![("level".to_owned(), "Level *1* to 6".to_owned())])
+            .build()
+            .value
+    }
+
+    #[test]
+    fn markdown_keeps_formatting() {
+        assert_eq!(
+            build(MarkupKind::Markdown),
+            "```daleth\nh level: body\n```\n\nA `heading`\n\n- `level`: Level *1* to 6"
+        );
+    }
+
+    fn reading_time(words: usize, words_per_minute: f64) -> String {
+        HoverBuilder::new(MarkupKind::PlainText)
+            .reading_time(words, words_per_minute)
+            .build()
+            .value
+    }
+
+    #[test]
+    fn reading_time_rounds_up_to_whole_minutes() {
+        assert_eq!(reading_time(450, 200.0), "450 words, about 3 min read");
+        assert_eq!(reading_time(3, 200.0), "3 words, about 1 min read");
+    }
+
+    #[test]
+    fn reading_time_without_a_speed_counts_words() {
+        assert_eq!(reading_time(450, 0.0), "450 words");
+        assert_eq!(reading_time(450, -100.0), "450 words");
+    }
+
+    #[test]
+    fn plaintext_strips_formatting() {
+        assert_eq!(
+            build(MarkupKind::PlainText),
+            "h level: body\n\nA heading\n\n- level: Level 1 to 6"
+        );
+    }
+}
@@ -0,0 +1,520 @@
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chumsky::Parser;
+use dalet::daleth::lexer::full_lexer;
+use ropey::Rope;
+use serde::Serialize;
+use tower_lsp::lsp_types::{Position, Range, TextEdit, Url};
+
+use crate::config::Config;
+use crate::position::{span_to_range, Encoding};
+use crate::tree::{Node, Tree};
+
+const LINK_TAGS: [&str; 4] = ["link", "navlink", "btn", "navbtn"];
+
+/// Anchor defined by an `a` tag.
+#[derive(Debug, Clone)]
+pub struct Anchor {
+    pub name: String,
+    /// Name argument of the tag.
+    pub range: Range,
+    pub number: bool,
+    /// Tag the anchor marks, which is the one following it, or the `a` tag
+    /// itself when it comes last.
+    pub content: Range,
+}
+
+/// Link pointing at an anchor, such as `link "#name"` or `link "page.dlt#name"`.
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub target: Url,
+    pub name: String,
+    pub range: Range,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FileIndex {
+    pub anchors: Vec<Anchor>,
+    pub references: Vec<Reference>,
+}
+
+impl FileIndex {
+    /// Anchors and links of `source`, with ranges counted in `encoding`.
+    pub fn new(uri: &Url, source: &str, encoding: Encoding) -> Option<Self> {
+        let tokens = full_lexer().parse(source).into_result().ok()?;
+        let tree = Tree::new(source, &tokens);
+        let rope = Rope::from_str(source);
+
+        let mut index = Self::default();
+        index.collect(uri, &tree.nodes, &rope, encoding);
+
+        Some(index)
+    }
+
+    fn collect(&mut self, uri: &Url, nodes: &[Node], rope: &Rope, encoding: Encoding) {
+        for (i, node) in nodes.iter().enumerate() {
+            match (node.name(), node.arguments.first()) {
+                ("a", Some(argument)) => {
+                    let span = if argument.number {
+                        argument.span.clone()
+                    } else {
+                        argument.span.start + 1..argument.span.end - 1
+                    };
+
+                    let content = nodes.get(i + 1).unwrap_or(node).span.clone();
+
+                    if let (Some(range), Some(content)) = (
+                        span_to_range(span, rope, encoding),
+                        span_to_range(content, rope, encoding),
+                    ) {
+                        self.anchors.push(Anchor {
+                            name: argument.value.clone(),
+                            range,
+                            number: argument.number,
+                            content,
+                        });
+                    }
+                }
+                (name, Some(argument)) if LINK_TAGS.contains(&name) && !argument.number => {
+                    let Some((path, fragment)) = argument.value.split_once('#') else {
+                        continue;
+                    };
+
+                    let target = if path.is_empty() {
+                        Some(uri.clone())
+                    } else if Url::parse(path).is_err() {
+                        uri.join(path).ok()
+                    } else {
+                        None
+                    };
+
+                    let start = argument.span.start + 1 + path.len() + 1;
+                    let range = span_to_range(start..start + fragment.len(), rope, encoding);
+
+                    if let (Some(target), Some(range)) = (target, range) {
+                        self.references.push(Reference {
+                            target,
+                            name: fragment.to_owned(),
+                            range,
+                        });
+                    }
+                }
+                _ => {}
+            }
+
+            self.collect(uri, node.children(), rope, encoding);
+        }
+    }
+
+    /// Anchor under `position` in this file, as `(file, name)`.
+    pub fn anchor_at(&self, uri: &Url, position: Position) -> Option<(Url, String, Range)> {
+        let contains = |range: &Range| range.start <= position && position <= range.end;
+
+        if let Some(anchor) = self.anchors.iter().find(|a| contains(&a.range)) {
+            return Some((uri.clone(), anchor.name.clone(), anchor.range));
+        }
+
+        self.references
+            .iter()
+            .find(|r| contains(&r.range))
+            .map(|r| (r.target.clone(), r.name.clone(), r.range))
+    }
+}
+
+/// Index of the Daleth files found in the workspace folders.
+#[derive(Debug, Default)]
+pub struct WorkspaceIndex {
+    pub files: HashMap<Url, FileIndex>,
+    /// Files that could not be indexed, with the reason.
+    pub failures: Vec<(PathBuf, String)>,
+}
+
+impl WorkspaceIndex {
+    pub fn build(roots: &[PathBuf], config: &Config, encoding: Encoding) -> Self {
+        Self::build_with(roots, config, &|uri, source| {
+            FileIndex::new(uri, source, encoding)
+        })
+    }
+
+    /// Like `build`, indexing each file with `index_file`.
+    fn build_with<F>(roots: &[PathBuf], config: &Config, index_file: &F) -> Self
+    where
+        F: Fn(&Url, &str) -> Option<FileIndex>,
+    {
+        let mut index = Self::default();
+
+        for root in roots {
+            index.walk(root, config, index_file);
+        }
+
+        index
+    }
+
+    /// Lists the files that could not be indexed, if any.
+    pub fn failure_report(&self) -> Option<String> {
+        if self.failures.is_empty() {
+            return None;
+        }
+
+        let failures = self
+            .failures
+            .iter()
+            .map(|(path, reason)| format!("\n  {}: {reason}", path.display()))
+            .collect::<String>();
+
+        let files = match self.failures.len() {
+            1 => "1 file".to_owned(),
+            n => format!("{n} files"),
+        };
+        Some(format!("{files} could not be indexed:{failures}"))
+    }
+
+    fn walk<F>(&mut self, dir: &Path, config: &Config, index_file: &F)
+    where
+        F: Fn(&Url, &str) -> Option<FileIndex>,
+    {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+
+            if path.is_dir() {
+                if !hidden {
+                    self.walk(&path, config, index_file);
+                }
+                continue;
+            }
+
+            let Ok(uri) = Url::from_file_path(&path) else {
+                continue;
+            };
+
+            if !config.is_associated(&uri) {
+                continue;
+            }
+
+            let source = match std::fs::read_to_string(&path) {
+                Ok(source) => source,
+                Err(error) => {
+                    self.failures.push((path, error.to_string()));
+                    continue;
+                }
+            };
+
+            // Each file is indexed in isolation so a panic in the lexer only
+            // loses that file
+            match std::panic::catch_unwind(AssertUnwindSafe(|| index_file(&uri, &source))) {
+                Ok(Some(file)) => {
+                    self.files.insert(uri, file);
+                }
+                Ok(None) => self.failures.push((path, "syntax error".to_owned())),
+                Err(panic) => {
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown error".to_owned());
+                    self.failures.push((path, format!("panicked: {message}")));
+                }
+            }
+        }
+    }
+}
+
+/// Lazily built workspace index that can be dropped after a period of inactivity.
+#[derive(Debug, Default)]
+pub struct IndexCache {
+    index: Option<Arc<WorkspaceIndex>>,
+    last_access: Option<Instant>,
+}
+
+impl IndexCache {
+    pub fn get(&mut self) -> Option<Arc<WorkspaceIndex>> {
+        self.last_access = Some(Instant::now());
+        self.index.clone()
+    }
+
+    pub fn set(&mut self, index: Arc<WorkspaceIndex>) {
+        self.index = Some(index);
+        self.last_access = Some(Instant::now());
+    }
+
+    pub fn clear(&mut self) {
+        self.index = None;
+    }
+
+    /// Returns true if the index was unloaded.
+    pub fn unload_if_idle(&mut self, idle: Duration) -> bool {
+        let idle = self.last_access.is_some_and(|last| last.elapsed() >= idle);
+
+        if idle && self.index.is_some() {
+            self.index = None;
+            return true;
+        }
+
+        false
+    }
+}
+
+/// Workspace index with the open documents taking precedence over disk.
+pub struct IndexView {
+    pub disk: Arc<WorkspaceIndex>,
+    pub open: HashMap<Url, FileIndex>,
+}
+
+impl IndexView {
+    pub fn files(&self) -> impl Iterator<Item = (&Url, &FileIndex)> {
+        self.open.iter().chain(
+            self.disk
+                .files
+                .iter()
+                .filter(|(uri, _)| !self.open.contains_key(uri)),
+        )
+    }
+
+    pub fn file(&self, uri: &Url) -> Option<&FileIndex> {
+        self.open.get(uri).or_else(|| self.disk.files.get(uri))
+    }
+
+    /// Anchor named by the anchor or link under `position`.
+    pub fn anchor(&self, uri: &Url, position: Position) -> Option<(Url, &Anchor)> {
+        let (target, name, _) = self.file(uri)?.anchor_at(uri, position)?;
+        let anchor = self
+            .file(&target)?
+            .anchors
+            .iter()
+            .find(|a| a.name == name)?;
+
+        Some((target, anchor))
+    }
+
+    pub fn rename(&self, target: &Url, name: &str, new_name: &str) -> Rename {
+        let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+        let mut conflicts = vec![];
+
+        let number = new_name.parse::<u8>().is_ok();
+
+        if let Some(file) = self.file(target) {
+            for anchor in file.anchors.iter() {
+                if anchor.name == new_name {
+                    conflicts.push(Conflict {
+                        uri: target.clone(),
+                        range: anchor.range,
+                        message: format!("Anchor `{new_name}` already exists"),
+                    });
+                }
+
+                if anchor.name != name {
+                    continue;
+                }
+
+                let new_text = match (anchor.number, number) {
+                    (true, false) => format!("\"{new_name}\""),
+                    _ => new_name.to_owned(),
+                };
+
+                changes
+                    .entry(target.clone())
+                    .or_default()
+                    .push(TextEdit::new(anchor.range, new_text));
+            }
+        }
+
+        for (uri, file) in self.files() {
+            for reference in file.references.iter() {
+                if &reference.target == target && reference.name == name {
+                    changes
+                        .entry(uri.clone())
+                        .or_default()
+                        .push(TextEdit::new(reference.range, new_name.to_owned()));
+                }
+            }
+        }
+
+        Rename { changes, conflicts }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Conflict {
+    pub uri: Url,
+    pub range: Range,
+    pub message: String,
+}
+
+pub struct Rename {
+    pub changes: HashMap<Url, Vec<TextEdit>>,
+    pub conflicts: Vec<Conflict>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(files: &[(&str, &str)]) -> IndexView {
+        let open = files
+            .iter()
+            .map(|(uri, source)| {
+                let uri = Url::parse(uri).unwrap();
+                let file = FileIndex::new(&uri, source, Encoding::Utf16).unwrap();
+                (uri, file)
+            })
+            .collect();
+
+        IndexView {
+            disk: Arc::default(),
+            open,
+        }
+    }
+
+    fn edits(rename: &Rename, uri: &str) -> Vec<(u32, u32, String)> {
+        let mut edits = rename.changes[&Url::parse(uri).unwrap()]
+            .iter()
+            .map(|edit| {
+                (
+                    edit.range.start.line,
+                    edit.range.start.character,
+                    edit.new_text.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        edits.sort();
+        edits
+    }
+
+    #[test]
+    fn panicking_files_are_skipped() {
+        let root = std::env::temp_dir().join(format!("dalet-lsp-index-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("nested")).unwrap();
+        std::fs::write(root.join("one.dlt"), "h1: One\n").unwrap();
+        std::fs::write(root.join("nested/two.dlt"), "h1: Two\n").unwrap();
+        std::fs::write(root.join("panic.dlt"), "h1: Panic\n").unwrap();
+
+        let index = WorkspaceIndex::build_with(
+            std::slice::from_ref(&root),
+            &Config::default(),
+            &|uri: &Url, source: &str| {
+                if source.contains("Panic") {
+                    panic!("lexer bug");
+                }
+                FileIndex::new(uri, source, Encoding::Utf16)
+            },
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let mut files = index
+            .files
+            .keys()
+            .map(|uri| {
+                uri.path()
+                    .strip_prefix(root.to_str().unwrap())
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files, ["/nested/two.dlt", "/one.dlt"]);
+        assert_eq!(
+            index.failures,
+            [(root.join("panic.dlt"), "panicked: lexer bug".to_owned())]
+        );
+        assert_eq!(
+            index.failure_report(),
+            Some(format!(
+                "1 file could not be indexed:\n  {}: panicked: lexer bug",
+                root.join("panic.dlt").display()
+            ))
+        );
+    }
+
+    #[test]
+    fn failure_report_counts_files() {
+        let index = WorkspaceIndex {
+            failures: vec![
+                (PathBuf::from("/a.dlt"), "too large".to_owned()),
+                (PathBuf::from("/b.dlt"), "unreadable".to_owned()),
+            ],
+            ..WorkspaceIndex::default()
+        };
+
+        assert_eq!(
+            index.failure_report().as_deref(),
+            Some("2 files could not be indexed:\n  /a.dlt: too large\n  /b.dlt: unreadable")
+        );
+        assert_eq!(WorkspaceIndex::default().failure_report(), None);
+    }
+
+    #[test]
+    fn idle_index_is_unloaded_until_set_again() {
+        let mut cache = IndexCache::default();
+        cache.set(Arc::default());
+        assert!(!cache.unload_if_idle(Duration::from_secs(60)));
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(cache.unload_if_idle(Duration::from_millis(10)));
+        assert!(cache.get().is_none());
+        assert!(!cache.unload_if_idle(Duration::from_millis(10)));
+
+        cache.set(Arc::default());
+        assert!(cache.get().is_some());
+    }
+
+    #[test]
+    fn rename_updates_links_in_other_files() {
+        let view = view(&[
+            (
+                "file:///docs/page.dlt",
+                "a \"intro\"\nh1: Intro\nlink \"#intro\": Top\n",
+            ),
+            ("file:///docs/index.dlt", "link \"page.dlt#intro\": Intro\n"),
+        ]);
+        let target = Url::parse("file:///docs/page.dlt").unwrap();
+
+        let rename = view.rename(&target, "intro", "start");
+
+        assert!(rename.conflicts.is_empty());
+        assert_eq!(
+            edits(&rename, "file:///docs/page.dlt"),
+            [(0, 3, "start".to_owned()), (2, 7, "start".to_owned())]
+        );
+        assert_eq!(
+            edits(&rename, "file:///docs/index.dlt"),
+            [(0, 15, "start".to_owned())]
+        );
+    }
+
+    #[test]
+    fn rename_to_an_existing_anchor_conflicts() {
+        let view = view(&[(
+            "file:///docs/page.dlt",
+            "a \"intro\"\nh1: Intro\na \"start\"\nh1: Start\n",
+        )]);
+        let target = Url::parse("file:///docs/page.dlt").unwrap();
+
+        let rename = view.rename(&target, "intro", "start");
+
+        assert_eq!(rename.conflicts.len(), 1);
+        assert_eq!(rename.conflicts[0].range.start, Position::new(2, 3));
+        assert_eq!(rename.conflicts[0].message, "Anchor `start` already exists");
+    }
+
+    #[test]
+    fn renaming_a_number_anchor_quotes_the_new_name() {
+        let view = view(&[("file:///docs/page.dlt", "a 1\nh1: Intro\n")]);
+        let target = Url::parse("file:///docs/page.dlt").unwrap();
+
+        let rename = view.rename(&target, "1", "intro");
+
+        assert_eq!(
+            edits(&rename, "file:///docs/page.dlt"),
+            [(0, 2, "\"intro\"".to_owned())]
+        );
+    }
+}
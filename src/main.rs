@@ -1,17 +1,61 @@
+mod abbreviation;
+mod ast;
+mod cli;
+mod completion;
+mod config;
+mod diagnostics;
+mod error;
+mod folding;
+mod formatter;
+mod hover;
+mod index;
+mod position;
+mod registration;
+mod schema;
+mod semantic;
+mod structure;
+mod symbols;
+mod tokens;
+mod tree;
+
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range as ByteRange;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
-use chumsky::input::Input;
 use chumsky::Parser;
-use dalet::daleth::format::format;
-use dalet::daleth::lexer::{full_lexer, lexer};
-use dalet::daleth::parser::parser;
+use dalet::daleth::lexer::full_lexer;
+use dalet::daleth::lexer::types::Token;
 use dalet::daleth::types::Spanned;
+use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
 use ropey::Rope;
-use serde_json::Value;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tokio::io::AsyncWriteExt;
 use tower_lsp::jsonrpc::{Error, ErrorCode, Result};
+use tower_lsp::lsp_types::request::{GotoDeclarationParams, GotoDeclarationResponse};
 use tower_lsp::lsp_types::*;
-use tower_lsp::{Client, LanguageServer, LspService, Server};
+use tower_lsp::{Client, ClientSocket, LanguageServer, LspService, Server};
+
+use crate::completion::Context;
+use crate::config::Config;
+use crate::diagnostics::{analyze, TYPOGRAPHIC, UNUSED_ANCHOR};
+use crate::error::ServerError;
+use crate::formatter::{format, verify, FormatOptions};
+use crate::hover::HoverBuilder;
+use crate::index::{FileIndex, IndexCache, IndexView, Rename, WorkspaceIndex};
+use crate::position::{
+    offset_to_position, position_to_offset, ranges_overlap, span_to_range, Encoding,
+};
+use crate::registration::{advertised, features};
+use crate::schema::{tag_schema, token_schema};
+use crate::symbols::{breadcrumb, document_symbols, structure, Crumb, StructureNode};
+use crate::tokens::{escapes, token_kind, trim_span, TokenInfo};
+use crate::tree::{Action, Body, Tree};
 
 struct TextDocumentItem {
     uri: Url,
@@ -19,66 +63,963 @@ struct TextDocumentItem {
     version: i32,
 }
 
+#[derive(Debug, Deserialize)]
+struct VisibleRangeParams {
+    uri: Url,
+    range: Range,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExpandAbbreviationParams {
+    uri: Url,
+    position: Position,
+    abbreviation: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct BreadcrumbParams {
+    uri: Url,
+    position: Position,
+}
+
+#[derive(Debug, Clone)]
+struct Document {
+    rope: Rope,
+    version: i32,
+}
+
+/// Commands that produce edits, unavailable in analysis-only mode.
+const MUTATING_COMMANDS: [&str; 5] = [
+    "daleth.formatDocument",
+    "daleth.moveNodeUp",
+    "daleth.moveNodeDown",
+    "daleth.replaceTag",
+    "daleth.anchorHeadings",
+];
+
 #[derive(Debug)]
 struct Backend {
     client: Client,
-    document_map: DashMap<String, Rope>,
+    document_map: DashMap<String, Document>,
+    config: Arc<RwLock<Config>>,
+    client_capabilities: RwLock<ClientCapabilities>,
+    /// Unit of position columns, negotiated in `initialize`.
+    encoding: RwLock<Encoding>,
+    visible_ranges: DashMap<String, Range>,
+    /// Latest diagnostics of each document and the version they were computed for.
+    diagnostics: DashMap<String, (i32, Vec<Diagnostic>)>,
+    workspace_roots: RwLock<Vec<PathBuf>>,
+    workspace_index: Arc<Mutex<IndexCache>>,
+    /// Dynamically registered methods and the options they were registered with.
+    registrations: Mutex<HashMap<&'static str, Value>>,
+    /// Last semantic tokens returned for each document, with their result id
+    /// and the version they were computed for.
+    semantic_tokens: DashMap<String, (String, i32, Vec<SemanticToken>)>,
+    semantic_tokens_ids: AtomicU64,
+    /// Multi-line text spans of each document that last lexed cleanly, and
+    /// its version.
+    text_regions: DashMap<String, (i32, Vec<ByteRange<usize>>)>,
+    /// Tag tree of each document and the version it was built for.
+    structures: DashMap<String, (i32, Vec<StructureNode>)>,
 }
 
 impl Backend {
+    fn config(&self) -> Config {
+        self.config.read().unwrap().clone()
+    }
+
+    fn encoding(&self) -> Encoding {
+        *self.encoding.read().unwrap()
+    }
+
+    fn hover_markup(&self) -> MarkupKind {
+        let capabilities = self.client_capabilities.read().unwrap();
+        let formats = capabilities
+            .text_document
+            .as_ref()
+            .and_then(|t| t.hover.as_ref())
+            .and_then(|h| h.content_format.as_ref());
+
+        match formats {
+            Some(formats) if formats.contains(&MarkupKind::Markdown) => MarkupKind::Markdown,
+            _ => MarkupKind::PlainText,
+        }
+    }
+
+    /// Registers the dynamic features that are enabled and unregisters the
+    /// ones that are not, re-registering those whose options changed.
+    async fn sync_registrations(&self) {
+        let features = features(&self.config(), &self.client_capabilities.read().unwrap());
+        let desired = features
+            .into_iter()
+            .filter(|feature| feature.dynamic && feature.enabled)
+            .map(|feature| (feature.method, feature.options))
+            .collect::<HashMap<_, _>>();
+
+        let (unregister, register) = {
+            let mut registered = self.registrations.lock().unwrap();
+
+            let unregister = registered
+                .iter()
+                .filter(|(method, options)| desired.get(*method) != Some(*options))
+                .map(|(method, _)| Unregistration {
+                    id: method.to_string(),
+                    method: method.to_string(),
+                })
+                .collect::<Vec<_>>();
+
+            let register = desired
+                .iter()
+                .filter(|(method, options)| registered.get(*method) != Some(*options))
+                .map(|(method, options)| Registration {
+                    id: method.to_string(),
+                    method: method.to_string(),
+                    register_options: Some(options.clone()),
+                })
+                .collect::<Vec<_>>();
+
+            *registered = desired;
+            (unregister, register)
+        };
+
+        if !unregister.is_empty() {
+            if let Err(error) = self.client.unregister_capability(unregister).await {
+                self.client
+                    .log_message(
+                        MessageType::WARNING,
+                        format!("failed to unregister capabilities: {error}"),
+                    )
+                    .await;
+            }
+        }
+
+        if !register.is_empty() {
+            if let Err(error) = self.client.register_capability(register).await {
+                self.client
+                    .log_message(
+                        MessageType::WARNING,
+                        format!("failed to register capabilities: {error}"),
+                    )
+                    .await;
+            }
+        }
+    }
+
+    fn ensure_writable(&self) -> Result<()> {
+        if self.config().analysis_only {
+            return Err(ServerError::AnalysisOnly.into());
+        }
+
+        Ok(())
+    }
+
+    fn documentation_markup(&self) -> MarkupKind {
+        let capabilities = self.client_capabilities.read().unwrap();
+        let formats = capabilities
+            .text_document
+            .as_ref()
+            .and_then(|t| t.completion.as_ref())
+            .and_then(|c| c.completion_item.as_ref())
+            .and_then(|i| i.documentation_format.as_ref());
+
+        match formats {
+            Some(formats) if formats.contains(&MarkupKind::Markdown) => MarkupKind::Markdown,
+            _ => MarkupKind::PlainText,
+        }
+    }
+
+    fn spawn_index_unloader(&self) {
+        let client = self.client.clone();
+        let config = self.config.clone();
+        let index = self.workspace_index.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let idle = config.read().unwrap().index_idle_unload();
+                let interval = idle.map_or(Duration::from_secs(60), |idle| {
+                    (idle / 2).clamp(Duration::from_millis(100), Duration::from_secs(60))
+                });
+
+                tokio::time::sleep(interval).await;
+
+                let unloaded = idle.is_some_and(|idle| index.lock().unwrap().unload_if_idle(idle));
+                if unloaded {
+                    client
+                        .log_message(MessageType::LOG, "unloaded idle workspace index")
+                        .await;
+                }
+            }
+        });
+    }
+
+    async fn index_view(&self) -> IndexView {
+        let cached = self.workspace_index.lock().unwrap().get();
+        let disk = match cached {
+            Some(index) => index,
+            None => {
+                let roots = self.workspace_roots.read().unwrap().clone();
+                let config = self.config();
+                let encoding = self.encoding();
+                let index = tokio::task::spawn_blocking(move || {
+                    WorkspaceIndex::build(&roots, &config, encoding)
+                })
+                .await
+                .map(Arc::new)
+                .unwrap_or_default();
+
+                if let Some(report) = index.failure_report() {
+                    self.client.log_message(MessageType::WARNING, report).await;
+                }
+
+                self.workspace_index.lock().unwrap().set(index.clone());
+                index
+            }
+        };
+
+        let config = self.config();
+        let open = self
+            .document_map
+            .iter()
+            .filter_map(|entry| {
+                let uri = Url::parse(entry.key()).ok()?;
+                if !config.is_associated(&uri) {
+                    return None;
+                }
+
+                let file = FileIndex::new(&uri, &entry.rope.to_string(), self.encoding())?;
+                Some((uri, file))
+            })
+            .collect();
+
+        IndexView { disk, open }
+    }
+
+    async fn rename_at(
+        &self,
+        uri: &Url,
+        position: Position,
+        new_name: &str,
+    ) -> Result<Option<Rename>> {
+        if new_name.is_empty()
+            || new_name
+                .chars()
+                .any(|c| c.is_whitespace() || matches!(c, '"' | '#' | '\\'))
+        {
+            return Err(Error::invalid_params(format!(
+                "`{new_name}` is not a valid anchor name"
+            )));
+        }
+
+        let view = self.index_view().await;
+        let Some((target, name, _)) = view
+            .file(uri)
+            .and_then(|file| file.anchor_at(uri, position))
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(view.rename(&target, &name, new_name)))
+    }
+
+    async fn preview_rename(&self, arguments: &[Value]) -> Result<Value> {
+        let uri = command_uri(arguments)?;
+        let (Some(position), Some(new_name)) = (
+            arguments
+                .get(1)
+                .and_then(|p| serde_json::from_value::<Position>(p.clone()).ok()),
+            arguments.get(2).and_then(Value::as_str),
+        ) else {
+            return Err(Error::invalid_params(
+                "Expected a document URI, a position and a new name",
+            ));
+        };
+
+        let Some(rename) = self.rename_at(&uri, position, new_name).await? else {
+            return Ok(Value::Null);
+        };
+
+        let mut files = rename
+            .changes
+            .iter()
+            .map(|(uri, edits)| json!({ "uri": uri, "editCount": edits.len() }))
+            .collect::<Vec<_>>();
+        files.sort_by_key(|file| file["uri"].as_str().map(ToOwned::to_owned));
+
+        Ok(json!({ "files": files, "conflicts": rename.conflicts }))
+    }
+
+    fn move_node(&self, arguments: &[Value], up: bool) -> Result<Value> {
+        let uri = command_uri(arguments)?;
+        let Some(position) = arguments
+            .get(1)
+            .and_then(|p| serde_json::from_value::<Position>(p.clone()).ok())
+        else {
+            return Err(Error::invalid_params(
+                "Expected a document URI and a position",
+            ));
+        };
+
+        let document = self.require_document(&uri)?;
+        let string = document.rope.to_string();
+        let options = self.config().format_options();
+        let tokens = lex(&string)?;
+        let tree = Tree::new(&string, &tokens);
+
+        let edits = position_to_offset(position, &document.rope, self.encoding())
+            .and_then(|offset| structure::move_node(&string, &tree, offset, up, &options))
+            .and_then(|(span, new_text)| {
+                Some(TextEdit::new(
+                    span_to_range(span, &document.rope, self.encoding())?,
+                    new_text,
+                ))
+            })
+            .into_iter()
+            .collect();
+
+        Ok(json!(document_edit(&uri, document.version, edits)))
+    }
+
+    fn replace_tag(&self, arguments: &[Value]) -> Result<Value> {
+        let uri = command_uri(arguments)?;
+        let (Some(from), Some(to)) = (
+            arguments.get(1).and_then(Value::as_str),
+            arguments.get(2).and_then(Value::as_str),
+        ) else {
+            return Err(Error::invalid_params(
+                "Expected a document URI, a tag to replace and its replacement",
+            ));
+        };
+
+        let from = tag_schema(from)
+            .ok_or_else(|| Error::invalid_params(format!("Unknown tag `{from}`")))?;
+        let to =
+            tag_schema(to).ok_or_else(|| Error::invalid_params(format!("Unknown tag `{to}`")))?;
+
+        let document = self.require_document(&uri)?;
+        let string = document.rope.to_string();
+        let tokens = lex(&string)?;
+        let tree = Tree::new(&string, &tokens);
+
+        let edits = structure::replace_tag(&string, &tree, from.name, to)
+            .map_err(Error::invalid_params)?
+            .into_iter()
+            .filter_map(|(span, new_text)| {
+                Some(TextEdit::new(
+                    span_to_range(span, &document.rope, self.encoding())?,
+                    new_text,
+                ))
+            })
+            .collect();
+
+        Ok(json!(document_edit(&uri, document.version, edits)))
+    }
+
+    fn anchor_headings(&self, uri: &Url) -> Result<Value> {
+        let document = self.require_document(uri)?;
+        let string = document.rope.to_string();
+        let tokens = lex(&string)?;
+        let tree = Tree::new(&string, &tokens);
+
+        let edits = structure::anchor_headings(&string, &tree)
+            .into_iter()
+            .filter_map(|(span, new_text)| {
+                Some(TextEdit::new(
+                    span_to_range(span, &document.rope, self.encoding())?,
+                    new_text,
+                ))
+            })
+            .collect();
+
+        Ok(json!(document_edit(uri, document.version, edits)))
+    }
+
+    /// The document without what the tree builder had to recover from, and
+    /// what was removed.
+    fn export_clean(&self, uri: &Url) -> Result<Value> {
+        let Document { rope, .. } = self.require_document(uri)?;
+        let string = rope.to_string();
+        let tokens = lex(&string)?;
+        let tree = Tree::new(&string, &tokens);
+
+        let (text, removals) =
+            structure::export_clean(&string, &tree, &self.config().format_options());
+        let removed = removals
+            .into_iter()
+            .map(|(span, message)| {
+                json!({
+                    "message": message,
+                    "range": span_to_range(span.clone(), &rope, self.encoding()),
+                    "text": &string[span],
+                })
+            })
+            .collect::<Vec<_>>();
+
+        Ok(json!({ "text": text, "removed": removed }))
+    }
+
+    /// Lists each place the tree builder had to recover and what it did.
+    fn parse_report(&self, uri: &Url) -> Result<Value> {
+        let Document { rope, .. } = self.require_document(uri)?;
+        let string = rope.to_string();
+
+        let (tokens, errors) = full_lexer().parse(&string).into_output_errors();
+        let Some(tokens) = tokens else {
+            // The lexer has no recovery and stops at its first error
+            let errors = errors
+                .iter()
+                .map(|error| {
+                    json!({
+                        "message": error.to_string(),
+                        "range": span_to_range(error.span().into_range(), &rope, self.encoding()),
+                        "action": "stopped",
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            return Ok(json!({ "stage": "lexer", "errors": errors }));
+        };
+
+        let tree = Tree::new(&string, &tokens);
+        let errors = tree
+            .recoveries
+            .iter()
+            .map(|recovery| {
+                let range = span_to_range(recovery.span.clone(), &rope, self.encoding());
+                match &recovery.action {
+                    Action::Skipped(skipped) => json!({
+                        "message": recovery.message,
+                        "range": range,
+                        "action": "skipped",
+                        "tokens": skipped
+                            .iter()
+                            .map(|&i| {
+                                let span = trim_span(&string, tokens[i].1);
+                                json!({
+                                    "kind": token_kind(&tokens[i].0),
+                                    "text": &string[span],
+                                })
+                            })
+                            .collect::<Vec<_>>(),
+                    }),
+                    Action::Inserted(text) => json!({
+                        "message": recovery.message,
+                        "range": range,
+                        "action": "inserted",
+                        "text": text,
+                    }),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Ok(json!({ "stage": "tree", "errors": errors }))
+    }
+
+    fn document(&self, uri: &Url) -> Option<Document> {
+        self.document_map.get(uri.as_str()).map(|d| d.clone())
+    }
+
+    /// The document at `uri`, or a [`ServerError::DocumentNotOpen`] error.
+    fn require_document(&self, uri: &Url) -> Result<Document> {
+        self.document(uri)
+            .ok_or_else(|| ServerError::DocumentNotOpen(uri.clone()).into())
+    }
+
+    /// Like [`Backend::require_document`], but files whose extension is not
+    /// associated are never analyzed, so requests on them get no result.
+    fn open_document(&self, uri: &Url) -> Result<Option<Document>> {
+        match self.document(uri) {
+            Some(document) => Ok(Some(document)),
+            None if !self.config().is_associated(uri) => Ok(None),
+            None => Err(ServerError::DocumentNotOpen(uri.clone()).into()),
+        }
+    }
+
+    /// Semantic tokens of the current version of a document under a new
+    /// result id, remembered as the base of the next delta.
+    fn compute_semantic_tokens(&self, uri: &Url) -> Option<SemanticTokens> {
+        let Document { rope, version } = self.document(uri)?;
+
+        let string = rope.to_string();
+        let tokens = full_lexer().parse(&string).into_result().ok()?;
+        let data = semantic::semantic_tokens(&string, &tokens, &rope, self.encoding());
+
+        let id = self.semantic_tokens_ids.fetch_add(1, Ordering::Relaxed) + 1;
+        let result_id = id.to_string();
+
+        self.semantic_tokens
+            .insert(uri.to_string(), (result_id.clone(), version, data.clone()));
+
+        Some(SemanticTokens {
+            result_id: Some(result_id),
+            data,
+        })
+    }
+
+    /// Fails if the document changed while edits for `version` were computed.
+    fn ensure_version(&self, uri: &Url, version: i32) -> Result<()> {
+        if self.document(uri).map(|d| d.version) != Some(version) {
+            return Err(Error {
+                code: ErrorCode::ContentModified,
+                message: Cow::Borrowed("Document changed during formatting"),
+                data: None,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns false if a newer version of the document is already stored.
+    fn update_document(&self, uri: &Url, document: Document) -> bool {
+        match self.document_map.entry(uri.to_string()) {
+            Entry::Occupied(entry) if entry.get().version > document.version => false,
+            Entry::Occupied(mut entry) => {
+                entry.insert(document);
+                true
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(document);
+                true
+            }
+        }
+    }
+
     async fn check_file(&self, params: TextDocumentItem) {
+        if !self.config().is_associated(&params.uri) {
+            self.client
+                .log_message(
+                    MessageType::LOG,
+                    format!("skipping {}: extension is not associated", params.uri),
+                )
+                .await;
+            return;
+        }
+
         self.client
             .log_message(MessageType::INFO, "run file check")
             .await;
 
-        let rope = ropey::Rope::from_str(&params.text);
+        let rope = Rope::from_str(&params.text);
+
+        let document = Document {
+            rope: rope.clone(),
+            version: params.version,
+        };
+
+        if !self.update_document(&params.uri, document) {
+            return;
+        }
+
+        match full_lexer().parse(&params.text).into_result() {
+            Ok(tokens) => {
+                let regions = completion::text_regions(&params.text, &tokens);
+                self.text_regions
+                    .insert(params.uri.to_string(), (params.version, regions));
+            }
+            Err(_) => {
+                self.text_regions.remove(params.uri.as_str());
+            }
+        }
+
+        let diagnostics = self.analyze(&params.uri, &params.text, &rope).await;
+        self.diagnostics.insert(
+            params.uri.to_string(),
+            (params.version, diagnostics.clone()),
+        );
+
+        // The diagnostics in view of a large document are published on their
+        // own first, and the rest only after other requests had a chance to run
+        let visible_range = self.visible_ranges.get(params.uri.as_str()).map(|r| *r);
+        if let Some(visible_range) = visible_range {
+            let visible = diagnostics
+                .iter()
+                .filter(|d| ranges_overlap(&d.range, &visible_range))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if visible.len() < diagnostics.len() {
+                self.publish_diagnostics(&params.uri, visible, Some(params.version))
+                    .await;
+
+                tokio::task::yield_now().await;
+                if self.document(&params.uri).map(|d| d.version) != Some(params.version) {
+                    return;
+                }
+            }
+        }
+
+        self.publish_diagnostics(&params.uri, diagnostics, Some(params.version))
+            .await;
+    }
+
+    /// Sends diagnostics to the client and/or appends them to the diagnostics
+    /// file, depending on the configured sink.
+    async fn publish_diagnostics(
+        &self,
+        uri: &Url,
+        diagnostics: Vec<Diagnostic>,
+        version: Option<i32>,
+    ) {
+        let config = self.config();
 
-        let mut errors: Vec<Spanned<String>> = vec![];
+        if config.diagnostics_sink.file() {
+            let result = match &config.diagnostics_file {
+                Some(path) => append_diagnostics(path, uri, &diagnostics, version).await,
+                None => Err(std::io::Error::other("diagnosticsFile is not set")),
+            };
 
-        let (tokens, lex_errors) = lexer().parse(&params.text).into_output_errors();
+            if let Err(error) = result {
+                self.client
+                    .log_message(
+                        MessageType::WARNING,
+                        format!("failed to write diagnostics: {error}"),
+                    )
+                    .await;
+            }
+        }
 
-        for error in lex_errors {
-            errors.push((error.to_string(), error.span().to_owned()));
+        if config.diagnostics_sink.client() {
+            self.client
+                .publish_diagnostics(uri.clone(), diagnostics, version)
+                .await;
         }
+    }
 
-        if let Some(tokens) = tokens {
-            let parse_errors = parser()
-                .parse(tokens.as_slice().spanned((0..params.text.len()).into()))
-                .into_errors();
+    async fn set_visible_range(&self, params: VisibleRangeParams) {
+        self.visible_ranges
+            .insert(params.uri.to_string(), params.range);
+    }
 
-            for error in parse_errors {
-                errors.push((error.to_string(), error.span().to_owned()));
+    /// Applies the edit produced by `compute`. A rejected edit is usually caused
+    /// by a version race, so it is recomputed against the current document and
+    /// applied once more before giving up.
+    async fn apply_edit_with_retry<F>(&self, compute: F) -> Result<bool>
+    where
+        F: Fn() -> Result<WorkspaceEdit>,
+    {
+        for _ in 0..2 {
+            match self.client.apply_edit(compute()?).await {
+                Ok(res) if res.applied => return Ok(true),
+                Ok(res) => {
+                    self.client
+                        .log_message(
+                            MessageType::LOG,
+                            format!("edit rejected: {}", res.failure_reason.unwrap_or_default()),
+                        )
+                        .await
+                }
+                Err(err) => {
+                    self.client.log_message(MessageType::ERROR, &err).await;
+                    return Err(err);
+                }
             }
         }
 
-        let diagnostics = errors
+        self.client
+            .show_message(MessageType::WARNING, "The edit was rejected by the editor")
+            .await;
+
+        Ok(false)
+    }
+
+    fn preview_formats(&self, uri: &Url) -> Result<Value> {
+        let Document { rope, .. } = self.require_document(uri)?;
+
+        let string = rope.to_string();
+        let tokens = lex(&string)?;
+
+        let styles = FormatOptions::styles()
             .into_iter()
-            .filter_map(|(message, span)| -> Option<Diagnostic> {
-                let start_position = offset_to_position(span.start, &rope)?;
-                let end_position = offset_to_position(span.end, &rope)?;
-                Some(Diagnostic::new(
-                    Range::new(start_position, end_position),
-                    Some(DiagnosticSeverity::ERROR),
-                    None,
-                    None,
-                    message,
-                    None,
-                    None,
+            .map(|(name, options)| (name.to_owned(), Value::String(format(&tokens, &options))))
+            .collect::<Map<_, _>>();
+
+        Ok(Value::Object(styles))
+    }
+
+    fn verify_format(&self, uri: &Url) -> Result<Value> {
+        let Document { rope, .. } = self.require_document(uri)?;
+
+        let options = self.config().format_options();
+        let differences =
+            verify(&rope.to_string(), |tokens| format(tokens, &options)).map_err(|message| {
+                Error {
+                    code: ErrorCode::InternalError,
+                    message: Cow::Borrowed(message),
+                    data: None,
+                }
+            })?;
+
+        Ok(json!({
+            "ok": differences.is_empty(),
+            "differences": differences,
+        }))
+    }
+
+    /// Diagnostics of a document, with the anchors linked to from anywhere in
+    /// the workspace when the unused anchor lint is on.
+    async fn analyze(&self, uri: &Url, source: &str, rope: &Rope) -> Vec<Diagnostic> {
+        let config = self.config();
+        let referenced = match config.unused_anchors {
+            true => Some(
+                self.index_view()
+                    .await
+                    .files()
+                    .flat_map(|(_, file)| file.references.iter())
+                    .filter(|reference| &reference.target == uri)
+                    .map(|reference| reference.name.clone())
+                    .collect::<HashSet<_>>(),
+            ),
+            false => None,
+        };
+
+        analyze(
+            uri,
+            source,
+            rope,
+            self.encoding(),
+            &config,
+            referenced.as_ref(),
+        )
+    }
+
+    /// Diagnostics of every open document, computed only where the cache is stale.
+    async fn all_diagnostics(&self) -> Result<HashMap<String, Vec<Diagnostic>>> {
+        let documents = self
+            .document_map
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect::<Vec<_>>();
+
+        let mut all = HashMap::new();
+        for (uri, document) in documents {
+            let cached = self
+                .diagnostics
+                .get(&uri)
+                .filter(|cached| cached.0 == document.version)
+                .map(|cached| cached.1.clone());
+
+            let diagnostics = match cached {
+                Some(diagnostics) => diagnostics,
+                None => {
+                    let Ok(url) = Url::parse(&uri) else {
+                        continue;
+                    };
+                    let diagnostics = self
+                        .analyze(&url, &document.rope.to_string(), &document.rope)
+                        .await;
+                    self.diagnostics
+                        .insert(uri.clone(), (document.version, diagnostics.clone()));
+                    diagnostics
+                }
+            };
+
+            all.insert(uri, diagnostics);
+        }
+
+        Ok(all)
+    }
+
+    /// Edit expanding an abbreviation at a position, replacing it when it is
+    /// the text right before the position.
+    async fn expand_abbreviation(&self, params: ExpandAbbreviationParams) -> Result<TextEdit> {
+        self.ensure_writable()?;
+
+        let Document { rope, .. } = self.require_document(&params.uri)?;
+        let string = rope.to_string();
+        let offset = position_to_offset(params.position, &rope, self.encoding())
+            .ok_or_else(|| Error::invalid_params("Position is outside the document"))?;
+
+        let line_start = string[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let before = &string[line_start..offset];
+        let indent = &before[..before.len() - before.trim_start().len()];
+
+        let options = self.config().format_options();
+        let expanded = abbreviation::expand(&params.abbreviation, &options.indent)
+            .map_err(|message| Error::invalid_params(format!("Invalid abbreviation: {message}")))?;
+
+        // The expansion uses the formatter layout, so it must lex and parse
+        // like formatted text
+        if analyze(
+            &params.uri,
+            &expanded,
+            &Rope::from_str(&expanded),
+            self.encoding(),
+            &Config::default(),
+            None,
+        )
+        .iter()
+        .any(|d| d.severity == Some(DiagnosticSeverity::ERROR))
+        {
+            return Err(Error::invalid_params(format!(
+                "`{}` does not expand to valid Daleth",
+                params.abbreviation
+            )));
+        }
+
+        let start = match before.ends_with(params.abbreviation.trim()) {
+            true => offset - params.abbreviation.trim().len(),
+            false => offset,
+        };
+
+        let new_text = expanded
+            .lines()
+            .enumerate()
+            .map(|(i, line)| match i {
+                0 => line.to_owned(),
+                _ => format!("{indent}{line}"),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let range = span_to_range(start..offset, &rope, self.encoding())
+            .ok_or_else(|| Error::invalid_params("Position is outside the document"))?;
+
+        Ok(TextEdit::new(range, new_text))
+    }
+
+    /// Edits making a document parse, built from the recoveries of the tree
+    /// builder, with how far they can be trusted.
+    async fn suggest_repair(&self, params: TextDocumentIdentifier) -> Result<Value> {
+        self.ensure_writable()?;
+
+        let Document { rope, version } = self.require_document(&params.uri)?;
+        let string = rope.to_string();
+
+        let Ok(tokens) = full_lexer().parse(&string).into_result() else {
+            return Ok(json!({
+                "edit": null,
+                "confidence": "none",
+                "note": "The lexer stops at its first error, so there is nothing to repair from",
+            }));
+        };
+
+        let tree = Tree::new(&string, &tokens);
+        if tree.recoveries.is_empty() {
+            return Ok(json!({
+                "edit": null,
+                "confidence": "none",
+                "note": "The tag tree needed no recovery",
+            }));
+        }
+
+        let (edits, parses) = structure::repair(&string, &tree);
+        let edits = edits
+            .into_iter()
+            .filter_map(|(span, new_text)| {
+                Some(TextEdit::new(
+                    span_to_range(span, &rope, self.encoding())?,
+                    new_text,
                 ))
             })
             .collect::<Vec<_>>();
 
-        self.document_map.insert(params.uri.to_string(), rope);
+        let (confidence, note) = match parses {
+            true => ("high", "The repaired document parses"),
+            false => ("low", "The repaired document still has syntax errors"),
+        };
 
-        self.client
-            .publish_diagnostics(params.uri.clone(), diagnostics, Some(params.version))
-            .await;
+        Ok(json!({
+            "edit": document_edit(&params.uri, version, edits),
+            "confidence": confidence,
+            "note": note,
+        }))
+    }
+
+    async fn structure(&self, params: TextDocumentIdentifier) -> Result<Vec<StructureNode>> {
+        let Document { rope, version } = self.require_document(&params.uri)?;
+
+        if let Some(cached) = self
+            .structures
+            .get(params.uri.as_str())
+            .filter(|cached| cached.0 == version)
+        {
+            return Ok(cached.1.clone());
+        }
+
+        let string = rope.to_string();
+        let tokens = lex(&string)?;
+        let nodes = structure(&Tree::new(&string, &tokens).nodes, &rope, self.encoding());
+
+        self.structures
+            .insert(params.uri.to_string(), (version, nodes.clone()));
+
+        Ok(nodes)
+    }
+
+    async fn breadcrumb(&self, params: BreadcrumbParams) -> Result<Vec<Crumb>> {
+        let Document { rope, .. } = self.require_document(&params.uri)?;
+        let offset = position_to_offset(params.position, &rope, self.encoding())
+            .ok_or_else(|| Error::invalid_params("Position is outside the document"))?;
+
+        let string = rope.to_string();
+        let tokens = lex(&string)?;
+        let tree = Tree::new(&string, &tokens);
+
+        Ok(breadcrumb(&tree.ancestors(offset), &rope, self.encoding()))
+    }
+
+    async fn tokens(&self, params: TextDocumentIdentifier) -> Result<Vec<TokenInfo>> {
+        let Document { rope, .. } = self.require_document(&params.uri)?;
+
+        let string = rope.to_string();
+        let tokens = match full_lexer().parse(&string).into_result() {
+            Ok(tokens) => tokens
+                .iter()
+                .filter_map(|(token, span)| {
+                    let span = trim_span(&string, *span);
+                    Some(TokenInfo {
+                        kind: token_kind(token),
+                        range: span_to_range(span.clone(), &rope, self.encoding())?,
+                        text: string[span].to_owned(),
+                    })
+                })
+                .collect(),
+            Err(errors) => errors
+                .iter()
+                .filter_map(|error| {
+                    let span = error.span();
+                    Some(TokenInfo {
+                        kind: "error",
+                        range: span_to_range(span.into_range(), &rope, self.encoding())?,
+                        text: string.get(span.into_range()).unwrap_or_default().to_owned(),
+                    })
+                })
+                .collect(),
+        };
+
+        Ok(tokens)
     }
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        if let Some(config) = params.initialization_options.and_then(Config::from_value) {
+            *self.config.write().unwrap() = config;
+        }
+        let config = self.config();
+        let writable = !config.analysis_only;
+        let features = features(&config, &params.capabilities);
+        let encoding = Encoding::negotiate(
+            params
+                .capabilities
+                .general
+                .as_ref()
+                .and_then(|g| g.position_encodings.as_deref()),
+        );
+        *self.encoding.write().unwrap() = encoding;
+        *self.client_capabilities.write().unwrap() = params.capabilities;
+
+        #[allow(deprecated)]
+        let roots = match (params.workspace_folders, params.root_uri) {
+            (Some(folders), _) => folders.into_iter().map(|f| f.uri).collect(),
+            (None, Some(root)) => vec![root],
+            (None, None) => vec![],
+        };
+        *self.workspace_roots.write().unwrap() = roots
+            .iter()
+            .filter_map(|uri| uri.to_file_path().ok())
+            .collect();
+
         Ok(InitializeResult {
             server_info: Some(ServerInfo {
                 name: "daleth-lsp".to_owned(),
@@ -86,6 +1027,8 @@ impl LanguageServer for Backend {
             }),
             offset_encoding: None,
             capabilities: ServerCapabilities {
+                position_encoding: Some(encoding.kind()),
+
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
                     TextDocumentSyncKind::FULL,
                 )),
@@ -98,10 +1041,76 @@ impl LanguageServer for Backend {
                     file_operations: None,
                 }),
 
-                document_formatting_provider: Some(OneOf::Left(true)),
+                document_formatting_provider: advertised(&features, "textDocument/formatting")
+                    .then_some(OneOf::Left(true)),
+                document_range_formatting_provider: advertised(
+                    &features,
+                    "textDocument/rangeFormatting",
+                )
+                .then_some(OneOf::Left(true)),
+
+                hover_provider: config
+                    .features
+                    .hover
+                    .then_some(HoverProviderCapability::Simple(true)),
+
+                completion_provider: config.features.completion.then_some(CompletionOptions {
+                    resolve_provider: Some(true),
+                    ..Default::default()
+                }),
+
+                document_symbol_provider: config
+                    .features
+                    .document_symbol
+                    .then_some(OneOf::Left(true)),
+
+                declaration_provider: config
+                    .features
+                    .declaration
+                    .then_some(DeclarationCapability::Simple(true)),
+
+                definition_provider: config.features.definition.then_some(OneOf::Left(true)),
+
+                folding_range_provider: config
+                    .features
+                    .folding_range
+                    .then_some(FoldingRangeProviderCapability::Simple(true)),
+
+                code_action_provider: (writable && config.features.code_action).then_some(
+                    CodeActionProviderCapability::Options(CodeActionOptions {
+                        code_action_kinds: Some(vec![CodeActionKind::QUICKFIX]),
+                        ..Default::default()
+                    }),
+                ),
+
+                semantic_tokens_provider: advertised(&features, "textDocument/semanticTokens")
+                    .then_some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+                        SemanticTokensOptions {
+                            legend: semantic::legend(),
+                            full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
+                            ..Default::default()
+                        },
+                    )),
+
+                rename_provider: (writable && config.features.rename).then_some(OneOf::Right(
+                    RenameOptions {
+                        prepare_provider: Some(true),
+                        work_done_progress_options: Default::default(),
+                    },
+                )),
 
                 execute_command_provider: Some(ExecuteCommandOptions {
-                    commands: vec!["dummy.do_something".to_string()],
+                    commands: [
+                        "daleth.previewFormats",
+                        "daleth.verifyFormat",
+                        "daleth.previewRename",
+                        "daleth.parseReport",
+                        "daleth.exportClean",
+                    ]
+                    .into_iter()
+                    .chain(writable.then_some(MUTATING_COMMANDS).into_iter().flatten())
+                    .map(ToOwned::to_owned)
+                    .collect(),
                     work_done_progress_options: Default::default(),
                 }),
 
@@ -113,47 +1122,529 @@ impl LanguageServer for Backend {
         self.client
             .log_message(MessageType::INFO, "initialized!")
             .await;
+
+        self.spawn_index_unloader();
+        self.sync_registrations().await;
     }
 
     async fn shutdown(&self) -> Result<()> {
         Ok(())
     }
 
-    async fn execute_command(&self, _: ExecuteCommandParams) -> Result<Option<Value>> {
-        self.client
-            .log_message(MessageType::INFO, "command executed!")
-            .await;
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        {
+            let mut roots = self.workspace_roots.write().unwrap();
+            let removed = params
+                .event
+                .removed
+                .iter()
+                .filter_map(|f| f.uri.to_file_path().ok())
+                .collect::<Vec<_>>();
+
+            roots.retain(|root| !removed.contains(root));
+            roots.extend(
+                params
+                    .event
+                    .added
+                    .iter()
+                    .filter_map(|f| f.uri.to_file_path().ok()),
+            );
+        }
+
+        self.workspace_index.lock().unwrap().clear();
+    }
+
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        if let Some(config) = Config::from_value(params.settings) {
+            *self.config.write().unwrap() = config;
+        }
+
+        self.sync_registrations().await;
+    }
 
-        match self.client.apply_edit(WorkspaceEdit::default()).await {
-            Ok(res) if res.applied => self.client.log_message(MessageType::INFO, "applied").await,
-            Ok(_) => self.client.log_message(MessageType::INFO, "rejected").await,
-            Err(err) => self.client.log_message(MessageType::ERROR, err).await,
+    async fn did_change_watched_files(&self, _: DidChangeWatchedFilesParams) {
+        self.workspace_index.lock().unwrap().clear();
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
+        if MUTATING_COMMANDS.contains(&params.command.as_str()) {
+            self.ensure_writable()?;
         }
 
-        Ok(None)
+        match params.command.as_str() {
+            "daleth.previewFormats" => {
+                let uri = command_uri(&params.arguments)?;
+                self.preview_formats(&uri).map(Some)
+            }
+            "daleth.formatDocument" => {
+                let uri = command_uri(&params.arguments)?;
+                let applied = self
+                    .apply_edit_with_retry(|| {
+                        let document = self.require_document(&uri)?;
+
+                        Ok(document_edit(
+                            &uri,
+                            document.version,
+                            format_edits(
+                                &document,
+                                &self.config().format_options(),
+                                self.encoding(),
+                            )?,
+                        ))
+                    })
+                    .await?;
+
+                Ok(Some(Value::Bool(applied)))
+            }
+            "daleth.previewRename" => self.preview_rename(&params.arguments).await.map(Some),
+            "daleth.moveNodeUp" => self.move_node(&params.arguments, true).map(Some),
+            "daleth.moveNodeDown" => self.move_node(&params.arguments, false).map(Some),
+            "daleth.replaceTag" => self.replace_tag(&params.arguments).map(Some),
+            "daleth.anchorHeadings" => {
+                let uri = command_uri(&params.arguments)?;
+                self.anchor_headings(&uri).map(Some)
+            }
+            "daleth.parseReport" => {
+                let uri = command_uri(&params.arguments)?;
+                self.parse_report(&uri).map(Some)
+            }
+            "daleth.exportClean" => {
+                let uri = command_uri(&params.arguments)?;
+                self.export_clean(&uri).map(Some)
+            }
+            "daleth.verifyFormat" => {
+                let uri = command_uri(&params.arguments)?;
+                self.verify_format(&uri).map(Some)
+            }
+            command => Err(Error::invalid_params(format!("Unknown command {command}"))),
+        }
     }
 
     async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        self.ensure_writable()?;
+        if !self.config().features.formatting {
+            return Ok(None);
+        }
+
         let uri = params.text_document.uri;
-        let rope = self.document_map.get(uri.as_str()).unwrap();
+        let Some(snapshot) = self.open_document(&uri)? else {
+            return Ok(None);
+        };
+
+        // Yielding lets a `$/cancelRequest` drop this request between the
+        // expensive steps instead of after the whole document is formatted
+        let string = snapshot.rope.to_string();
+        let tokens = lex(&string)?;
+        tokio::task::yield_now().await;
+
+        let formatted = format(&tokens, &self.config().format_options());
+        tokio::task::yield_now().await;
+
+        let edits = vec![full_document_edit(
+            &snapshot.rope,
+            &string,
+            formatted,
+            self.encoding(),
+        )];
+
+        self.ensure_version(&uri, snapshot.version)?;
+        Ok(Some(edits))
+    }
+
+    async fn range_formatting(
+        &self,
+        params: DocumentRangeFormattingParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        self.ensure_writable()?;
+        if !self.config().features.range_formatting {
+            return Ok(None);
+        }
+
+        let Some(Document { rope, version }) = self.open_document(&params.text_document.uri)?
+        else {
+            return Ok(None);
+        };
+        let (Some(start), Some(end)) = (
+            position_to_offset(params.range.start, &rope, self.encoding()),
+            position_to_offset(params.range.end, &rope, self.encoding()),
+        ) else {
+            return Ok(None);
+        };
 
+        // Checkpoints like in `formatting`
         let string = rope.to_string();
-        let lexed = full_lexer().parse(&string);
+        let tokens = lex(&string)?;
+        let tree = Tree::new(&string, &tokens);
+        tokio::task::yield_now().await;
 
-        match lexed.into_result() {
-            Ok(t) => Ok(Some(vec![TextEdit {
-                range: Range::new(
-                    offset_to_position(0, &rope).unwrap(),
-                    offset_to_position(string.len(), &rope).unwrap(),
-                ),
-                new_text: format(&t),
-            }])),
-            Err(_) => Err(Error {
-                code: ErrorCode::InternalError,
-                message: Cow::Borrowed("Lexer error"),
-                data: None,
-            }),
+        let edits =
+            structure::format_range(&string, &tree, start..end, &self.config().format_options())
+                .and_then(|(span, new_text)| {
+                    Some(TextEdit::new(
+                        span_to_range(span, &rope, self.encoding())?,
+                        new_text,
+                    ))
+                })
+                .into_iter()
+                .collect();
+        tokio::task::yield_now().await;
+
+        self.ensure_version(&params.text_document.uri, version)?;
+        Ok(Some(edits))
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        if !self.config().features.hover {
+            return Ok(None);
+        }
+
+        let position = params.text_document_position_params;
+        let Some(Document { rope, .. }) = self.open_document(&position.text_document.uri)? else {
+            return Ok(None);
+        };
+        let Some(offset) = position_to_offset(position.position, &rope, self.encoding()) else {
+            return Ok(None);
+        };
+
+        let string = rope.to_string();
+        let Ok(tokens) = full_lexer().parse(&string).into_result() else {
+            return Ok(None);
+        };
+        let tree = Tree::new(&string, &tokens);
+        let words_per_minute = self.config().reading_words_per_minute;
+
+        let hover = tokens.iter().find_map(|(token, span)| {
+            let span = trim_span(&string, *span);
+            if !span.contains(&offset) {
+                return None;
+            }
+
+            let builder = HoverBuilder::new(self.hover_markup());
+
+            if let Some((span, c)) = escapes(&string, token, span.clone())
+                .into_iter()
+                .find(|(span, _)| span.contains(&offset))
+            {
+                return Some(Hover {
+                    contents: HoverContents::Markup(
+                        builder.escape(&string[span.clone()], c).build(),
+                    ),
+                    range: Some(span_to_range(span, &rope, self.encoding())?),
+                });
+            }
+
+            let schema = token_schema(token)?;
+            let mut builder = builder.tag(schema);
+
+            if let Some(node) = tree
+                .node_by_tag_span(&span)
+                .filter(|node| matches!(node.body, Body::Tags { .. }))
+            {
+                builder = builder.reading_time(node.word_count(), words_per_minute);
+            }
+
+            Some(Hover {
+                contents: HoverContents::Markup(builder.build()),
+                range: Some(span_to_range(span, &rope, self.encoding())?),
+            })
+        });
+
+        Ok(hover)
+    }
+
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        if !self.config().features.completion {
+            return Ok(None);
+        }
+
+        let position = params.text_document_position;
+        let uri = position.text_document.uri;
+        let Some(Document { rope, version }) = self.open_document(&uri)? else {
+            return Ok(None);
+        };
+        let Some(offset) = position_to_offset(position.position, &rope, self.encoding()) else {
+            return Ok(None);
+        };
+
+        // The last clean lex knows every multi-line text, so the cursor line
+        // is enough to judge the rest. Without it a few lines above are
+        // scanned for a text left open.
+        let regions = self
+            .text_regions
+            .get(uri.as_str())
+            .filter(|cached| cached.0 == version)
+            .map(|cached| cached.1.iter().any(|r| r.start < offset && offset < r.end));
+
+        let line = rope.byte_to_line(offset);
+        let context = match regions {
+            Some(true) => Context::Text,
+            Some(false) => {
+                completion::context(&rope.byte_slice(rope.line_to_byte(line)..offset).to_string())
+            }
+            None => {
+                let first = rope.line_to_byte(line.saturating_sub(completion::CONTEXT_LINES));
+                completion::context(&rope.byte_slice(first..offset).to_string())
+            }
+        };
+
+        let close = match context {
+            Context::Comment => None,
+            _ => completion::close_item(rope.byte_slice(..offset).chars()),
+        };
+        let tags = match context {
+            Context::TagName => completion::tag_items(),
+            _ => vec![],
+        };
+
+        let items = close.into_iter().chain(tags).collect();
+        Ok(Some(CompletionResponse::Array(items)))
+    }
+
+    async fn completion_resolve(&self, item: CompletionItem) -> Result<CompletionItem> {
+        Ok(completion::resolve(item, self.documentation_markup()))
+    }
+
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<Option<DocumentSymbolResponse>> {
+        if !self.config().features.document_symbol {
+            return Ok(None);
+        }
+
+        let Some(Document { rope, .. }) = self.open_document(&params.text_document.uri)? else {
+            return Ok(None);
+        };
+
+        let string = rope.to_string();
+        let Ok(tokens) = full_lexer().parse(&string).into_result() else {
+            return Ok(None);
+        };
+
+        let tree = Tree::new(&string, &tokens);
+
+        Ok(Some(DocumentSymbolResponse::Nested(document_symbols(
+            &tree.nodes,
+            &rope,
+            self.encoding(),
+        ))))
+    }
+
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        if !self.config().features.folding_range {
+            return Ok(None);
+        }
+
+        let Some(Document { rope, .. }) = self.open_document(&params.text_document.uri)? else {
+            return Ok(None);
+        };
+
+        let string = rope.to_string();
+        let Ok(tokens) = full_lexer().parse(&string).into_result() else {
+            return Ok(None);
+        };
+
+        let tree = Tree::new(&string, &tokens);
+
+        Ok(Some(folding::folding_ranges(
+            &string,
+            &tokens,
+            &tree.nodes,
+            &rope,
+        )))
+    }
+
+    /// The name of the anchor under the cursor, or the one a link points at.
+    async fn goto_declaration(
+        &self,
+        params: GotoDeclarationParams,
+    ) -> Result<Option<GotoDeclarationResponse>> {
+        if !self.config().features.declaration {
+            return Ok(None);
+        }
+
+        let position = params.text_document_position_params;
+        let view = self.index_view().await;
+
+        Ok(view
+            .anchor(&position.text_document.uri, position.position)
+            .map(|(uri, anchor)| GotoDeclarationResponse::Scalar(Location::new(uri, anchor.range))))
+    }
+
+    /// The tag marked by the anchor under the cursor, or by the one a link
+    /// points at.
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        if !self.config().features.definition {
+            return Ok(None);
+        }
+
+        let position = params.text_document_position_params;
+        let view = self.index_view().await;
+
+        Ok(view
+            .anchor(&position.text_document.uri, position.position)
+            .map(|(uri, anchor)| {
+                GotoDefinitionResponse::Scalar(Location::new(uri, anchor.content))
+            }))
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<Option<SemanticTokensResult>> {
+        if !self.config().features.semantic_tokens
+            || self.open_document(&params.text_document.uri)?.is_none()
+        {
+            return Ok(None);
+        }
+
+        Ok(self
+            .compute_semantic_tokens(&params.text_document.uri)
+            .map(SemanticTokensResult::Tokens))
+    }
+
+    async fn semantic_tokens_full_delta(
+        &self,
+        params: SemanticTokensDeltaParams,
+    ) -> Result<Option<SemanticTokensFullDeltaResult>> {
+        if !self.config().features.semantic_tokens {
+            return Ok(None);
+        }
+
+        let uri = params.text_document.uri;
+        let Some(version) = self.open_document(&uri)?.map(|d| d.version) else {
+            return Ok(None);
+        };
+
+        let previous = self
+            .semantic_tokens
+            .get(uri.as_str())
+            .filter(|cached| cached.0 == params.previous_result_id)
+            .map(|cached| (cached.1, cached.2.clone()));
+
+        // A result id other than the last one returned cannot be diffed
+        // against, so the client gets full tokens instead
+        let Some((previous_version, previous)) = previous else {
+            return Ok(self
+                .compute_semantic_tokens(&uri)
+                .map(SemanticTokensFullDeltaResult::Tokens));
+        };
+
+        if previous_version == version {
+            return Ok(Some(SemanticTokensFullDeltaResult::TokensDelta(
+                SemanticTokensDelta {
+                    result_id: Some(params.previous_result_id),
+                    edits: vec![],
+                },
+            )));
+        }
+
+        Ok(self.compute_semantic_tokens(&uri).map(|tokens| {
+            SemanticTokensFullDeltaResult::TokensDelta(SemanticTokensDelta {
+                result_id: tokens.result_id,
+                edits: semantic::delta(&previous, &tokens.data),
+            })
+        }))
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let config = self.config();
+        if config.analysis_only || !config.features.code_action {
+            return Ok(None);
+        }
+
+        let uri = params.text_document.uri;
+        let actions = params
+            .context
+            .diagnostics
+            .iter()
+            .filter_map(|diagnostic| {
+                let data = diagnostic.data.as_ref()?;
+                let (title, edit) = match diagnostic.code.as_ref()? {
+                    NumberOrString::String(code) if code == TYPOGRAPHIC => {
+                        let replacement = data.get("replacement")?.as_str()?;
+                        (
+                            format!("Replace with `{replacement}`"),
+                            TextEdit::new(diagnostic.range, replacement.to_owned()),
+                        )
+                    }
+                    NumberOrString::String(code) if code == UNUSED_ANCHOR => {
+                        let removal = serde_json::from_value(data.get("removal")?.clone()).ok()?;
+                        (
+                            "Remove unused anchor".to_owned(),
+                            TextEdit::new(removal, String::new()),
+                        )
+                    }
+                    _ => return None,
+                };
+
+                Some(CodeActionOrCommand::CodeAction(CodeAction {
+                    title,
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![diagnostic.clone()]),
+                    edit: Some(WorkspaceEdit::new(HashMap::from([(
+                        uri.clone(),
+                        vec![edit],
+                    )]))),
+                    is_preferred: Some(true),
+                    ..Default::default()
+                }))
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Some(actions))
+    }
+
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>> {
+        let config = self.config();
+        if config.analysis_only || !config.features.rename {
+            return Ok(None);
+        }
+
+        let uri = params.text_document.uri;
+        let Some(Document { rope, .. }) = self.open_document(&uri)? else {
+            return Ok(None);
+        };
+
+        let range = FileIndex::new(&uri, &rope.to_string(), self.encoding())
+            .and_then(|file| file.anchor_at(&uri, params.position))
+            .map(|(_, _, range)| PrepareRenameResponse::Range(range));
+
+        Ok(range)
+    }
+
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        self.ensure_writable()?;
+        if !self.config().features.rename {
+            return Ok(None);
         }
+
+        let position = params.text_document_position;
+        let Some(rename) = self
+            .rename_at(
+                &position.text_document.uri,
+                position.position,
+                &params.new_name,
+            )
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        if let Some(conflict) = rename.conflicts.first() {
+            return Err(Error::invalid_params(format!(
+                "{} in {}",
+                conflict.message, conflict.uri
+            )));
+        }
+
+        Ok(Some(WorkspaceEdit::new(rename.changes)))
     }
 
     async fn did_change(&self, mut params: DidChangeTextDocumentParams) {
@@ -169,6 +1660,10 @@ impl LanguageServer for Backend {
         self.client
             .log_message(MessageType::INFO, "file opened")
             .await;
+        // A reopened document starts its versions over
+        self.semantic_tokens
+            .remove(params.text_document.uri.as_str());
+        self.structures.remove(params.text_document.uri.as_str());
         self.check_file(TextDocumentItem {
             uri: params.text_document.uri,
             text: params.text_document.text,
@@ -176,25 +1671,293 @@ impl LanguageServer for Backend {
         })
         .await
     }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri.as_str();
+
+        self.document_map.remove(uri);
+        self.diagnostics.remove(uri);
+        self.visible_ranges.remove(uri);
+        self.semantic_tokens.remove(uri);
+        self.text_regions.remove(uri);
+        self.structures.remove(uri);
+    }
 }
 
 #[tokio::main]
 async fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
+    }
+
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let (service, socket) = LspService::build(|client| Backend {
+    let (service, socket) = service();
+    Server::new(stdin, stdout, socket).serve(service).await;
+}
+
+fn service() -> (LspService<Backend>, ClientSocket) {
+    LspService::build(|client| Backend {
         client,
         document_map: DashMap::new(),
+        config: Arc::new(RwLock::new(Config::default())),
+        client_capabilities: RwLock::new(ClientCapabilities::default()),
+        encoding: RwLock::new(Encoding::default()),
+        visible_ranges: DashMap::new(),
+        diagnostics: DashMap::new(),
+        workspace_roots: RwLock::new(vec![]),
+        workspace_index: Arc::new(Mutex::new(IndexCache::default())),
+        registrations: Mutex::new(HashMap::new()),
+        semantic_tokens: DashMap::new(),
+        semantic_tokens_ids: AtomicU64::new(0),
+        text_regions: DashMap::new(),
+        structures: DashMap::new(),
     })
-    .finish();
+    .custom_method("daleth/tokens", Backend::tokens)
+    .custom_method("daleth/structure", Backend::structure)
+    .custom_method("daleth/breadcrumb", Backend::breadcrumb)
+    .custom_method("daleth/setVisibleRange", Backend::set_visible_range)
+    .custom_method("daleth/allDiagnostics", Backend::all_diagnostics)
+    .custom_method("daleth/suggestRepair", Backend::suggest_repair)
+    .custom_method("daleth/expandAbbreviation", Backend::expand_abbreviation)
+    .finish()
+}
 
-    Server::new(stdin, stdout, socket).serve(service).await;
+fn format_edits(
+    document: &Document,
+    options: &FormatOptions,
+    encoding: Encoding,
+) -> Result<Vec<TextEdit>> {
+    let string = document.rope.to_string();
+    let tokens = lex(&string)?;
+
+    Ok(vec![full_document_edit(
+        &document.rope,
+        &string,
+        format(&tokens, options),
+        encoding,
+    )])
 }
 
-fn offset_to_position(offset: usize, rope: &Rope) -> Option<Position> {
-    let line = rope.try_char_to_line(offset).ok()?;
-    let first_char_of_line = rope.try_line_to_char(line).ok()?;
-    let column = offset - first_char_of_line;
-    Some(Position::new(line as u32, column as u32))
+fn lex(string: &str) -> Result<Vec<Spanned<Token<'_>>>> {
+    full_lexer()
+        .parse(string)
+        .into_result()
+        .map_err(|_| ServerError::Lexer.into())
+}
+
+fn full_document_edit(rope: &Rope, string: &str, new_text: String, encoding: Encoding) -> TextEdit {
+    TextEdit {
+        range: Range::new(
+            offset_to_position(0, rope, encoding).unwrap(),
+            offset_to_position(string.len(), rope, encoding).unwrap(),
+        ),
+        new_text,
+    }
+}
+
+/// Edit of a single document, versioned so that clients reject it if the
+/// document changed in the meantime.
+fn document_edit(uri: &Url, version: i32, edits: Vec<TextEdit>) -> WorkspaceEdit {
+    WorkspaceEdit {
+        document_changes: Some(DocumentChanges::Edits(vec![TextDocumentEdit {
+            text_document: OptionalVersionedTextDocumentIdentifier {
+                uri: uri.clone(),
+                version: Some(version),
+            },
+            edits: edits.into_iter().map(OneOf::Left).collect(),
+        }])),
+        ..WorkspaceEdit::default()
+    }
+}
+
+async fn append_diagnostics(
+    path: &Path,
+    uri: &Url,
+    diagnostics: &[Diagnostic],
+    version: Option<i32>,
+) -> std::io::Result<()> {
+    let mut line = json!({
+        "uri": uri,
+        "version": version,
+        "diagnostics": diagnostics,
+    })
+    .to_string();
+    line.push('\n');
+
+    tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?
+        .write_all(line.as_bytes())
+        .await
+}
+
+fn command_uri(arguments: &[Value]) -> Result<Url> {
+    arguments
+        .first()
+        .and_then(Value::as_str)
+        .and_then(|uri| Url::parse(uri).ok())
+        .ok_or_else(|| Error::invalid_params("Expected a document URI as the first argument"))
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types;
+
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context as TaskContext, Waker};
+
+    use futures::StreamExt;
+
+    use super::*;
+
+    fn formatting_params(uri: &Url) -> DocumentFormattingParams {
+        DocumentFormattingParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            options: FormattingOptions::default(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        }
+    }
+
+    fn change(uri: &Url, version: i32, text: &str) -> DidChangeTextDocumentParams {
+        DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(uri.clone(), version),
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: text.to_owned(),
+            }],
+        }
+    }
+
+    /// A backend whose messages to the client are discarded.
+    fn backend() -> LspService<Backend> {
+        let (service, socket) = service();
+        tokio::spawn(socket.for_each(|_| async {}));
+        service
+    }
+
+    async fn open(backend: &Backend, uri: &Url, text: &str) {
+        backend
+            .did_open(DidOpenTextDocumentParams {
+                text_document: lsp_types::TextDocumentItem::new(
+                    uri.clone(),
+                    "daleth".to_owned(),
+                    1,
+                    text.to_owned(),
+                ),
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn formatting_is_rejected_when_a_change_interleaves() {
+        let service = backend();
+        let backend = service.inner();
+        let uri = Url::parse("file:///format.dlt").unwrap();
+        open(backend, &uri, "h1:   Old\n").await;
+
+        let mut formatting = pin!(backend.formatting(formatting_params(&uri)));
+        let mut context = TaskContext::from_waker(Waker::noop());
+        assert!(formatting.as_mut().poll(&mut context).is_pending());
+
+        backend.did_change(change(&uri, 2, "h1:   New\n")).await;
+
+        let error = formatting.await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::ContentModified);
+    }
+
+    #[tokio::test]
+    async fn range_formatting_is_rejected_when_a_change_interleaves() {
+        let service = backend();
+        let backend = service.inner();
+        let uri = Url::parse("file:///format.dlt").unwrap();
+        open(backend, &uri, "row [\np: Old\n]\n").await;
+
+        let mut formatting = pin!(backend.range_formatting(DocumentRangeFormattingParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            range: Range::new(Position::new(0, 0), Position::new(2, 1)),
+            options: FormattingOptions::default(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        }));
+        let mut context = TaskContext::from_waker(Waker::noop());
+        assert!(formatting.as_mut().poll(&mut context).is_pending());
+
+        backend
+            .did_change(change(&uri, 2, "row [\np: New\n]\n"))
+            .await;
+
+        let error = formatting.await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::ContentModified);
+    }
+
+    #[tokio::test]
+    async fn formatting_after_a_change_reflects_the_new_content() {
+        let service = backend();
+        let backend = service.inner();
+        let uri = Url::parse("file:///format.dlt").unwrap();
+        open(backend, &uri, "h1:   Old\n").await;
+        backend.did_change(change(&uri, 2, "h1:   New\n")).await;
+
+        let edits = backend
+            .formatting(formatting_params(&uri))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(edits[0].new_text.contains("New"));
+    }
+
+    /// Range of the only tag in `row [ p: é ]`, from a backend initialized
+    /// with `encodings`.
+    async fn symbol_range(backend: &Backend, encodings: Vec<PositionEncodingKind>) -> Range {
+        let result = backend
+            .initialize(InitializeParams {
+                capabilities: ClientCapabilities {
+                    general: Some(GeneralClientCapabilities {
+                        position_encodings: Some(encodings),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(result.capabilities.position_encoding.is_some());
+
+        let uri = Url::parse("file:///encoding.dlt").unwrap();
+        open(backend, &uri, "row [\n    p: \u{e9}\n]\n").await;
+
+        let symbols = backend
+            .document_symbol(DocumentSymbolParams {
+                text_document: TextDocumentIdentifier::new(uri),
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+            })
+            .await
+            .unwrap();
+        match symbols {
+            Some(DocumentSymbolResponse::Nested(symbols)) => {
+                symbols[0].children.as_ref().unwrap()[0].range
+            }
+            symbols => panic!("unexpected symbols {symbols:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn each_backend_keeps_its_own_encoding() {
+        let (utf8, utf16) = (backend(), backend());
+
+        let utf8_range = symbol_range(utf8.inner(), vec![PositionEncodingKind::UTF8]).await;
+        let utf16_range = symbol_range(utf16.inner(), vec![PositionEncodingKind::UTF16]).await;
+
+        assert_eq!(utf8_range.end, Position::new(1, 9));
+        assert_eq!(utf16_range.end, Position::new(1, 8));
+        assert_eq!(utf8_range.start, utf16_range.start);
+    }
 }
@@ -0,0 +1,148 @@
+use std::ops::Range as ByteRange;
+
+use ropey::Rope;
+use tower_lsp::lsp_types::{Position, PositionEncodingKind, Range};
+
+/// Unit that position columns count, negotiated with the client. UTF-16 is
+/// the default required by the protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    Utf8,
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl Encoding {
+    /// The first encoding offered by the client, or UTF-16 when it offers none.
+    pub fn negotiate(offered: Option<&[PositionEncodingKind]>) -> Self {
+        offered
+            .into_iter()
+            .flatten()
+            .find_map(|kind| match kind.as_str() {
+                "utf-8" => Some(Self::Utf8),
+                "utf-16" => Some(Self::Utf16),
+                "utf-32" => Some(Self::Utf32),
+                _ => None,
+            })
+            .unwrap_or(Self::Utf16)
+    }
+
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            Self::Utf8 => PositionEncodingKind::UTF8,
+            Self::Utf16 => PositionEncodingKind::UTF16,
+            Self::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    fn len(self, c: char) -> usize {
+        match self {
+            Self::Utf8 => c.len_utf8(),
+            Self::Utf16 => c.len_utf16(),
+            Self::Utf32 => 1,
+        }
+    }
+}
+
+/// Converts a byte offset, as used by the lexer spans, to a position with a
+/// column in `encoding`.
+pub fn offset_to_position(offset: usize, rope: &Rope, encoding: Encoding) -> Option<Position> {
+    let char = rope.try_byte_to_char(offset).ok()?;
+    let line = rope.try_char_to_line(char).ok()?;
+    let first_char_of_line = rope.try_line_to_char(line).ok()?;
+
+    let column = match encoding {
+        Encoding::Utf8 => rope.char_to_byte(char) - rope.char_to_byte(first_char_of_line),
+        Encoding::Utf16 => rope.char_to_utf16_cu(char) - rope.char_to_utf16_cu(first_char_of_line),
+        Encoding::Utf32 => char - first_char_of_line,
+    };
+    Some(Position::new(line as u32, column as u32))
+}
+
+/// Converts a position with a column in `encoding` to a byte offset. A
+/// column past the end of the line stands for the end of the line, as the
+/// protocol requires.
+pub fn position_to_offset(position: Position, rope: &Rope, encoding: Encoding) -> Option<usize> {
+    let line = rope.get_line(position.line as usize)?;
+    let first_char_of_line = rope.try_line_to_char(position.line as usize).ok()?;
+
+    let mut units = 0;
+    let column = line
+        .chars()
+        .take_while(|c| *c != '\n' && *c != '\r')
+        .take_while(|c| {
+            units += encoding.len(*c) as u32;
+            units <= position.character
+        })
+        .count();
+
+    rope.try_char_to_byte(first_char_of_line + column).ok()
+}
+
+/// Number of characters in `line` before `column`, counted in `encoding`.
+pub fn column_to_char(line: &str, column: u32, encoding: Encoding) -> usize {
+    let mut units = 0;
+
+    line.chars()
+        .take_while(|c| {
+            units += encoding.len(*c) as u32;
+            units <= column
+        })
+        .count()
+}
+
+pub fn span_to_range(span: ByteRange<usize>, rope: &Rope, encoding: Encoding) -> Option<Range> {
+    Some(Range::new(
+        offset_to_position(span.start, rope, encoding)?,
+        offset_to_position(span.end, rope, encoding)?,
+    ))
+}
+
+pub fn ranges_overlap(a: &Range, b: &Range) -> bool {
+    a.start <= b.end && b.start <= a.end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE: &str = "a\u{e9}\u{1F600}b\r\nnext\n";
+
+    #[test]
+    fn columns_follow_the_encoding() {
+        let rope = Rope::from_str(LINE);
+        let b = LINE.find('b').unwrap();
+
+        for (encoding, column) in [
+            (Encoding::Utf8, 7),
+            (Encoding::Utf16, 4),
+            (Encoding::Utf32, 3),
+        ] {
+            let position = Position::new(0, column);
+            assert_eq!(offset_to_position(b, &rope, encoding), Some(position));
+            assert_eq!(position_to_offset(position, &rope, encoding), Some(b));
+        }
+    }
+
+    #[test]
+    fn columns_past_the_line_end_are_clamped() {
+        let rope = Rope::from_str(LINE);
+        let end = LINE.find('\r').unwrap();
+
+        for encoding in [Encoding::Utf8, Encoding::Utf16, Encoding::Utf32] {
+            assert_eq!(
+                position_to_offset(Position::new(0, 100), &rope, encoding),
+                Some(end)
+            );
+        }
+        assert_eq!(
+            position_to_offset(Position::new(1, 100), &rope, Encoding::Utf16),
+            Some(LINE.len() - 1)
+        );
+        assert_eq!(
+            position_to_offset(Position::new(5, 0), &rope, Encoding::Utf16),
+            None
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use serde_json::{json, Value};
+use tower_lsp::lsp_types::ClientCapabilities;
+
+use crate::config::Config;
+use crate::semantic;
+
+/// A capability the server can register dynamically.
+pub struct Feature {
+    pub method: &'static str,
+    pub enabled: bool,
+    /// The client supports registering it at runtime, so it is left out of
+    /// the `initialize` result.
+    pub dynamic: bool,
+    pub options: Value,
+}
+
+impl Feature {
+    /// Whether to advertise the feature in the `initialize` result.
+    pub fn advertised(&self) -> bool {
+        self.enabled && !self.dynamic
+    }
+}
+
+pub fn features(config: &Config, capabilities: &ClientCapabilities) -> Vec<Feature> {
+    let text_document = capabilities.text_document.as_ref();
+    let workspace = capabilities.workspace.as_ref();
+    let selector = json!([{ "pattern": config.glob() }]);
+
+    vec![
+        Feature {
+            method: "textDocument/formatting",
+            enabled: !config.analysis_only && config.features.formatting,
+            dynamic: text_document
+                .and_then(|t| t.formatting.as_ref())
+                .and_then(|f| f.dynamic_registration)
+                .unwrap_or(false),
+            options: json!({ "documentSelector": selector }),
+        },
+        Feature {
+            method: "textDocument/rangeFormatting",
+            enabled: !config.analysis_only && config.features.range_formatting,
+            dynamic: text_document
+                .and_then(|t| t.range_formatting.as_ref())
+                .and_then(|f| f.dynamic_registration)
+                .unwrap_or(false),
+            options: json!({ "documentSelector": selector }),
+        },
+        Feature {
+            method: "textDocument/semanticTokens",
+            enabled: config.features.semantic_tokens,
+            dynamic: text_document
+                .and_then(|t| t.semantic_tokens.as_ref())
+                .and_then(|s| s.dynamic_registration)
+                .unwrap_or(false),
+            options: json!({
+                "documentSelector": selector,
+                "legend": semantic::legend(),
+                "full": { "delta": true },
+            }),
+        },
+        Feature {
+            method: "workspace/didChangeWatchedFiles",
+            enabled: true,
+            dynamic: workspace
+                .and_then(|w| w.did_change_watched_files.as_ref())
+                .and_then(|d| d.dynamic_registration)
+                .unwrap_or(false),
+            options: json!({ "watchers": [{ "globPattern": config.glob() }] }),
+        },
+    ]
+}
+
+pub fn advertised(features: &[Feature], method: &str) -> bool {
+    features
+        .iter()
+        .any(|feature| feature.method == method && feature.advertised())
+}
@@ -0,0 +1,394 @@
+use dalet::daleth::lexer::types::Token;
+
+use crate::tokens::tag_name;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgKind {
+    Text,
+    TextOrNumber,
+    HeadingLevel,
+    Align,
+}
+
+impl ArgKind {
+    pub fn accepts(self, value: &str, number: bool) -> bool {
+        match self {
+            Self::Text => !number,
+            Self::TextOrNumber => true,
+            Self::HeadingLevel => number && matches!(value, "1" | "2" | "3" | "4" | "5" | "6"),
+            Self::Align => !number && matches!(value, "start" | "center" | "end"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BodyKind {
+    None,
+    Text,
+    Tags,
+    TextOrTags,
+    OptionalTextOrTags,
+}
+
+impl BodyKind {
+    pub fn accepts_text(self) -> bool {
+        matches!(
+            self,
+            Self::Text | Self::TextOrTags | Self::OptionalTextOrTags
+        )
+    }
+
+    pub fn accepts_tags(self) -> bool {
+        matches!(
+            self,
+            Self::Tags | Self::TextOrTags | Self::OptionalTextOrTags
+        )
+    }
+
+    pub fn optional(self) -> bool {
+        matches!(self, Self::None | Self::OptionalTextOrTags)
+    }
+}
+
+#[derive(Debug)]
+pub struct ArgSchema {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub kind: ArgKind,
+    pub optional: bool,
+}
+
+#[derive(Debug)]
+pub struct TagSchema {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub arguments: &'static [ArgSchema],
+    pub body: BodyKind,
+    pub example: Option<&'static str>,
+}
+
+impl TagSchema {
+    /// Minimum and maximum number of arguments.
+    pub fn arity(&self) -> (usize, usize) {
+        let required = self.arguments.iter().filter(|a| !a.optional).count();
+        (required, self.arguments.len())
+    }
+
+    /// Such as `1 argument` or `0 to 1 arguments`.
+    pub fn expected_arguments(&self) -> String {
+        match self.arity() {
+            (1, 1) => "1 argument".to_owned(),
+            (min, max) if min == max => format!("{min} arguments"),
+            (min, max) => format!("{min} to {max} arguments"),
+        }
+    }
+
+    pub fn signature(&self) -> String {
+        let mut signature = self.name.to_owned();
+
+        for argument in self.arguments {
+            let name = match argument.kind {
+                ArgKind::HeadingLevel => argument.name.to_owned(),
+                _ => format!("\"{}\"", argument.name),
+            };
+
+            if argument.optional {
+                signature.push_str(&format!(" [{name}]"));
+            } else {
+                signature.push_str(&format!(" {name}"));
+            }
+        }
+
+        signature.push_str(match self.body {
+            BodyKind::None => "",
+            BodyKind::Text => ": text",
+            BodyKind::Tags => " [ tags ]",
+            BodyKind::TextOrTags => ": text | [ tags ]",
+            BodyKind::OptionalTextOrTags => " [: text | [ tags ]]",
+        });
+
+        signature
+    }
+}
+
+const URL: ArgSchema = ArgSchema {
+    name: "url",
+    description: "Target URL",
+    kind: ArgKind::Text,
+    optional: false,
+};
+
+const ALIGN: ArgSchema = ArgSchema {
+    name: "align",
+    description: "One of `start`, `center` or `end`, defaults to `start`",
+    kind: ArgKind::Align,
+    optional: true,
+};
+
+const FOOTNOTE: ArgSchema = ArgSchema {
+    name: "id",
+    description: "Footnote number or name",
+    kind: ArgKind::TextOrNumber,
+    optional: false,
+};
+
+pub const TAGS: &[TagSchema] = &[
+    TagSchema {
+        name: "el",
+        description: "Generic element",
+        arguments: &[],
+        body: BodyKind::TextOrTags,
+        example: Some("el [\n    - First\n    - Second\n]"),
+    },
+    TagSchema {
+        name: "h",
+        description: "Heading",
+        arguments: &[ArgSchema {
+            name: "level",
+            description: "Heading level from 1 to 6",
+            kind: ArgKind::HeadingLevel,
+            optional: false,
+        }],
+        body: BodyKind::Text,
+        example: Some("h1: Daleth"),
+    },
+    TagSchema {
+        name: "p",
+        description: "Paragraph",
+        arguments: &[],
+        body: BodyKind::TextOrTags,
+        example: Some("p: Simple paragraph"),
+    },
+    TagSchema {
+        name: "br",
+        description: "Line break",
+        arguments: &[],
+        body: BodyKind::None,
+        example: Some("- First line\nbr\n- Second line"),
+    },
+    TagSchema {
+        name: "ul",
+        description: "Unordered list",
+        arguments: &[],
+        body: BodyKind::Tags,
+        example: Some("ul [\n    - Apples\n    - Oranges\n]"),
+    },
+    TagSchema {
+        name: "ol",
+        description: "Ordered list",
+        arguments: &[],
+        body: BodyKind::Tags,
+        example: Some("ol [\n    - First\n    - Second\n]"),
+    },
+    TagSchema {
+        name: "row",
+        description: "Row of elements",
+        arguments: &[ALIGN],
+        body: BodyKind::Tags,
+        example: Some("row \"center\" [\n    - Left\n    - Right\n]"),
+    },
+    TagSchema {
+        name: "link",
+        description: "Link",
+        arguments: &[URL],
+        body: BodyKind::OptionalTextOrTags,
+        example: Some("link \"https://example.com\": Example"),
+    },
+    TagSchema {
+        name: "navlink",
+        description: "Navigation link",
+        arguments: &[URL],
+        body: BodyKind::OptionalTextOrTags,
+        example: Some("navlink \"/about\": About"),
+    },
+    TagSchema {
+        name: "btn",
+        description: "Button",
+        arguments: &[URL],
+        body: BodyKind::OptionalTextOrTags,
+        example: Some("btn \"https://example.com/donate\": Donate"),
+    },
+    TagSchema {
+        name: "navbtn",
+        description: "Navigation button",
+        arguments: &[URL],
+        body: BodyKind::OptionalTextOrTags,
+        example: Some("navbtn \"/\": Home"),
+    },
+    TagSchema {
+        name: "img",
+        description: "Image",
+        arguments: &[ArgSchema {
+            name: "src",
+            description: "Image URL",
+            kind: ArgKind::Text,
+            optional: false,
+        }],
+        body: BodyKind::None,
+        example: Some("img \"https://example.com/image.png\""),
+    },
+    TagSchema {
+        name: "table",
+        description: "Table",
+        arguments: &[],
+        body: BodyKind::Tags,
+        example: Some("{> table\n[[ Name | Value ]]\n[ a | 1 ]\n}"),
+    },
+    TagSchema {
+        name: "trow",
+        description: "Table row",
+        arguments: &[],
+        body: BodyKind::Tags,
+        example: Some("table [\n    trow [\n        - a\n        - 1\n    ]\n]"),
+    },
+    TagSchema {
+        name: "tprow",
+        description: "Table primary row",
+        arguments: &[],
+        body: BodyKind::Tags,
+        example: Some("table [\n    tprow [\n        - Name\n        - Value\n    ]\n    trow [\n        - a\n        - 1\n    ]\n]"),
+    },
+    TagSchema {
+        name: "hr",
+        description: "Horizontal rule",
+        arguments: &[],
+        body: BodyKind::None,
+        example: None,
+    },
+    TagSchema {
+        name: "b",
+        description: "Bold text",
+        arguments: &[],
+        body: BodyKind::Text,
+        example: Some("b: Bold text"),
+    },
+    TagSchema {
+        name: "i",
+        description: "Italic text",
+        arguments: &[],
+        body: BodyKind::Text,
+        example: Some("i: Italic text"),
+    },
+    TagSchema {
+        name: "bq",
+        description: "Blockquote",
+        arguments: &[],
+        body: BodyKind::TextOrTags,
+        example: None,
+    },
+    TagSchema {
+        name: "footlnk",
+        description: "Footnote link",
+        arguments: &[FOOTNOTE],
+        body: BodyKind::None,
+        example: Some("- See the note\nfootlnk 1"),
+    },
+    TagSchema {
+        name: "footn",
+        description: "Footnote",
+        arguments: &[FOOTNOTE],
+        body: BodyKind::Text,
+        example: Some("footn 1: The note"),
+    },
+    TagSchema {
+        name: "a",
+        description: "Anchor",
+        arguments: &[ArgSchema {
+            name: "id",
+            description: "Anchor name or number",
+            kind: ArgKind::TextOrNumber,
+            optional: false,
+        }],
+        body: BodyKind::None,
+        example: Some("a \"section\""),
+    },
+    TagSchema {
+        name: "s",
+        description: "Strikethrough text",
+        arguments: &[],
+        body: BodyKind::Text,
+        example: Some("s: Struck text"),
+    },
+    TagSchema {
+        name: "sup",
+        description: "Superscript",
+        arguments: &[],
+        body: BodyKind::Text,
+        example: None,
+    },
+    TagSchema {
+        name: "sub",
+        description: "Subscript",
+        arguments: &[],
+        body: BodyKind::Text,
+        example: None,
+    },
+    TagSchema {
+        name: "disc",
+        description: "Disclosure",
+        arguments: &[],
+        body: BodyKind::TextOrTags,
+        example: Some("disc [\n    - Hidden content\n]"),
+    },
+    TagSchema {
+        name: "block",
+        description: "Block",
+        arguments: &[ALIGN],
+        body: BodyKind::TextOrTags,
+        example: None,
+    },
+    TagSchema {
+        name: "carousel",
+        description: "Carousel",
+        arguments: &[],
+        body: BodyKind::Tags,
+        example: Some(
+            "carousel [\n    img \"https://example.com/1.png\"\n    img \"https://example.com/2.png\"\n]",
+        ),
+    },
+    TagSchema {
+        name: "code",
+        description: "Code block",
+        arguments: &[ArgSchema {
+            name: "language",
+            description: "Language of the code",
+            kind: ArgKind::Text,
+            optional: true,
+        }],
+        body: BodyKind::Text,
+        example: Some("code \"rust\" {\n    fn main() {\\}\n}"),
+    },
+    TagSchema {
+        name: "pre",
+        description: "Preformatted text",
+        arguments: &[],
+        body: BodyKind::Text,
+        example: None,
+    },
+    TagSchema {
+        name: "meta",
+        description: "Page metadata",
+        arguments: &[ArgSchema {
+            name: "key",
+            description: "Metadata key, such as `title` or `description`",
+            kind: ArgKind::Text,
+            optional: false,
+        }],
+        body: BodyKind::Text,
+        example: Some("meta \"title\": Page title"),
+    },
+];
+
+pub fn tag_schema(name: &str) -> Option<&'static TagSchema> {
+    TAGS.iter().find(|tag| tag.name == name)
+}
+
+/// Schema of the tag a token starts, including the tagless shorthands.
+pub fn token_schema(token: &Token) -> Option<&'static TagSchema> {
+    match token {
+        Token::TextTag(_) | Token::ElOpen => tag_schema("el"),
+        Token::Paragraph(_) => tag_schema("p"),
+        Token::TableSyntax(_) => tag_schema("table"),
+        token => tag_schema(tag_name(token)?),
+    }
+}
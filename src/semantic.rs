@@ -0,0 +1,282 @@
+use std::ops::Range as ByteRange;
+
+use dalet::daleth::lexer::types::Token;
+use dalet::daleth::types::Spanned;
+use ropey::Rope;
+use tower_lsp::lsp_types::{
+    SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokensEdit,
+    SemanticTokensLegend,
+};
+
+use crate::position::{span_to_range, Encoding};
+use crate::tokens::{escapes, tag_name, trim_span};
+use crate::tree::{Node, Tree};
+
+const TYPES: [SemanticTokenType; 5] = [
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::STRING,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::COMMENT,
+    SemanticTokenType::REGEXP,
+];
+
+const KEYWORD: u32 = 0;
+const STRING: u32 = 1;
+const NUMBER: u32 = 2;
+const COMMENT: u32 = 3;
+const ESCAPE: u32 = 4;
+
+/// Bit of the `escape` modifier, set on every escape sequence token.
+const ESCAPE_MODIFIER: u32 = 1;
+/// Bit of the `invalid` modifier, set on tags with the wrong number of
+/// arguments, which the `argument-count` diagnostic reports.
+const INVALID_MODIFIER: u32 = 2;
+
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TYPES.to_vec(),
+        token_modifiers: vec![
+            SemanticTokenModifier::new("escape"),
+            SemanticTokenModifier::new("invalid"),
+        ],
+    }
+}
+
+/// Tags, arguments, text bodies and comments, with escape sequences split out
+/// of the text around them.
+pub fn semantic_tokens(
+    source: &str,
+    tokens: &[Spanned<Token>],
+    rope: &Rope,
+    encoding: Encoding,
+) -> Vec<SemanticToken> {
+    let mut invalid = vec![];
+    invalid_tags(&Tree::new(source, tokens).nodes, &mut invalid);
+
+    let mut spans = vec![];
+
+    for (token, span) in tokens {
+        let span = trim_span(source, *span);
+
+        let kind = match token {
+            Token::TextArgument(_)
+            | Token::TextBody(_)
+            | Token::MLText(_)
+            | Token::MLMSText(_, _)
+            | Token::MLRText(_)
+            | Token::Paragraph(_) => Some(STRING),
+            Token::NumberArgument(_) => Some(NUMBER),
+            Token::Comment(_) => Some(COMMENT),
+            token if tag_name(token).is_some() => Some(KEYWORD),
+            _ => None,
+        };
+
+        // The colon of a one-line body is syntax, not text
+        let mut start = match token {
+            Token::TextBody(_) => {
+                let body = &source[span.clone()];
+                span.end - body.trim_start_matches(':').trim_start().len()
+            }
+            _ => span.start,
+        };
+        for (escape, _) in escapes(source, token, span.clone()) {
+            if let Some(kind) = kind {
+                spans.push((start..escape.start, kind, 0));
+            }
+            start = escape.end;
+            spans.push((escape, ESCAPE, ESCAPE_MODIFIER));
+        }
+
+        let modifiers = match kind == Some(KEYWORD) && invalid.contains(&span) {
+            true => INVALID_MODIFIER,
+            false => 0,
+        };
+        if let Some(kind) = kind {
+            spans.push((start..span.end, kind, modifiers));
+        }
+    }
+
+    let mut result = vec![];
+    let (mut line, mut character) = (0, 0);
+
+    // Multi-line bodies are split into a token per line, since a token may
+    // not cross a line
+    let spans = spans.into_iter().flat_map(|(span, token_type, modifiers)| {
+        lines(source, span).map(move |line| (line, token_type, modifiers))
+    });
+
+    for (span, token_type, modifiers) in spans {
+        if span.is_empty() {
+            continue;
+        }
+
+        let Some(range) = span_to_range(span, rope, encoding) else {
+            continue;
+        };
+
+        let delta_line = range.start.line - line;
+        let delta_start = match delta_line {
+            0 => range.start.character - character,
+            _ => range.start.character,
+        };
+
+        result.push(SemanticToken {
+            delta_line,
+            delta_start,
+            length: range.end.character - range.start.character,
+            token_type,
+            token_modifiers_bitset: modifiers,
+        });
+
+        line = range.start.line;
+        character = range.start.character;
+    }
+
+    result
+}
+
+/// Parts of `span` on each line, without the line breaks.
+fn lines(source: &str, span: ByteRange<usize>) -> impl Iterator<Item = ByteRange<usize>> + '_ {
+    let mut start = span.start;
+
+    source[span].split_inclusive('\n').map(move |line| {
+        let text = line.trim_end_matches(['\n', '\r']);
+        let part = start..start + text.len();
+        start += line.len();
+        part
+    })
+}
+
+fn invalid_tags(nodes: &[Node], spans: &mut Vec<ByteRange<usize>>) {
+    for node in nodes {
+        if !node.has_valid_arity() {
+            spans.push(node.tag_span.clone());
+        }
+        invalid_tags(node.children(), spans);
+    }
+}
+
+/// Edit turning `previous` into `current`, replacing the tokens between their
+/// common prefix and suffix. Offsets count integers, five per token.
+pub fn delta(previous: &[SemanticToken], current: &[SemanticToken]) -> Vec<SemanticTokensEdit> {
+    let prefix = previous
+        .iter()
+        .zip(current)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let suffix = previous[prefix..]
+        .iter()
+        .rev()
+        .zip(current[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let deleted = previous.len() - prefix - suffix;
+    let inserted = &current[prefix..current.len() - suffix];
+
+    if deleted == 0 && inserted.is_empty() {
+        return vec![];
+    }
+
+    vec![SemanticTokensEdit {
+        start: prefix as u32 * 5,
+        delete_count: deleted as u32 * 5,
+        data: Some(inserted.to_vec()),
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use chumsky::Parser;
+    use dalet::daleth::lexer::full_lexer;
+
+    use super::*;
+
+    /// Tokens as the text they cover, their type and modifiers.
+    fn tokens(source: &str) -> Vec<(String, u32, u32)> {
+        let tokens = full_lexer().parse(source).into_result().unwrap();
+        let lines = source.lines().collect::<Vec<_>>();
+        let (mut line, mut character) = (0, 0);
+
+        semantic_tokens(source, &tokens, &Rope::from_str(source), Encoding::Utf16)
+            .into_iter()
+            .map(|token| {
+                if token.delta_line > 0 {
+                    character = 0;
+                }
+                line += token.delta_line as usize;
+                character += token.delta_start as usize;
+
+                let text = lines[line]
+                    .chars()
+                    .skip(character)
+                    .take(token.length as usize)
+                    .collect();
+                (text, token.token_type, token.token_modifiers_bitset)
+            })
+            .collect()
+    }
+
+    fn token(text: &str, token_type: u32, modifiers: u32) -> (String, u32, u32) {
+        (text.to_owned(), token_type, modifiers)
+    }
+
+    #[test]
+    fn escapes_split_arguments() {
+        assert_eq!(
+            tokens("img \"a\\\"b\"\n"),
+            [
+                token("img", KEYWORD, 0),
+                token("\"a", STRING, 0),
+                token("\\\"", ESCAPE, ESCAPE_MODIFIER),
+                token("b\"", STRING, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn escapes_split_bodies() {
+        assert_eq!(
+            tokens("code \"rust\" {x\\}y}\n")[2..],
+            [
+                token("{x", STRING, 0),
+                token("\\}", ESCAPE, ESCAPE_MODIFIER),
+                token("y}", STRING, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn bodies_are_strings_on_each_line() {
+        assert_eq!(
+            tokens("h1: Notes\np {\n    One\n    Two\n}\n"),
+            [
+                token("h", KEYWORD, 0),
+                token("1", NUMBER, 0),
+                token("Notes", STRING, 0),
+                token("p", KEYWORD, 0),
+                token("{", STRING, 0),
+                token("    One", STRING, 0),
+                token("    Two", STRING, 0),
+                token("}", STRING, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn tags_with_the_wrong_arity_are_marked_invalid() {
+        assert_eq!(
+            tokens("img\nbr\nimg \"a.png\"\nimg \"a\" \"b\"\n"),
+            [
+                token("img", KEYWORD, INVALID_MODIFIER),
+                token("br", KEYWORD, 0),
+                token("img", KEYWORD, 0),
+                token("\"a.png\"", STRING, 0),
+                token("img", KEYWORD, INVALID_MODIFIER),
+                token("\"a\"", STRING, 0),
+                token("\"b\"", STRING, 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,689 @@
+use std::collections::HashSet;
+use std::ops::Range as ByteRange;
+
+use chumsky::input::Input;
+use chumsky::Parser;
+use dalet::daleth::lexer::{full_lexer, lexer};
+use dalet::daleth::parser::parser;
+
+use crate::formatter::{code_bodies, format, FormatOptions};
+use crate::schema::TagSchema;
+use crate::tree::{Action, Body, Node, Tree};
+
+/// Swaps the node at `offset` with its previous or next sibling. Returns the
+/// replaced byte range and its new text, or `None` at the boundary.
+pub fn move_node(
+    source: &str,
+    tree: &Tree,
+    offset: usize,
+    up: bool,
+    options: &FormatOptions,
+) -> Option<(ByteRange<usize>, String)> {
+    let (siblings, index) = tree.siblings_at(offset)?;
+    let other = if up { index.checked_sub(1)? } else { index + 1 };
+
+    let (first, second) = (
+        siblings.get(index.min(other))?,
+        siblings.get(index.max(other))?,
+    );
+
+    let indent = line_indent(source, first.span.start);
+    let between = &source[first.span.end..second.span.start];
+
+    let new_text = format!(
+        "{}{between}{}",
+        emit(source, second.span.clone(), indent, options)?,
+        emit(source, first.span.clone(), indent, options)?
+    );
+
+    Some((first.span.start..second.span.end, new_text))
+}
+
+/// Formats the tags overlapping `range`, widened to whole tags. The returned
+/// edit is trimmed to the bytes that actually change, so it never reaches
+/// outside the widened span.
+pub fn format_range(
+    source: &str,
+    tree: &Tree,
+    range: ByteRange<usize>,
+    options: &FormatOptions,
+) -> Option<(ByteRange<usize>, String)> {
+    let mut depth = 0;
+    let mut container = None;
+    let mut siblings = tree.nodes.as_slice();
+
+    while let Some(node) = siblings
+        .iter()
+        .find(|node| node.span.start <= range.start && range.end <= node.span.end)
+    {
+        // Descend only while the range stays between the brackets of a tag
+        // and touches at least one child
+        let inside = match &node.body {
+            Body::Tags { open, close, .. } => {
+                open.end <= range.start && close.as_ref().is_none_or(|c| range.end <= c.start)
+            }
+            _ => false,
+        };
+        let touches_child = node
+            .children()
+            .iter()
+            .any(|child| child.span.start <= range.end && range.start <= child.span.end);
+
+        if !inside || !touches_child {
+            container = Some(node);
+            break;
+        }
+
+        depth += 1;
+        siblings = node.children();
+    }
+
+    let selected = match container {
+        Some(node) => std::slice::from_ref(node),
+        None => {
+            let start = siblings.iter().position(|n| range.start <= n.span.end)?;
+            let end = siblings.iter().rposition(|n| n.span.start <= range.end)?;
+            siblings.get(start..=end)?
+        }
+    };
+
+    let mut span = selected.first()?.span.start..selected.last()?.span.end;
+    let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    if source[line_start..span.start].trim().is_empty() {
+        span.start = line_start;
+    }
+
+    let indent = options.indent.repeat(depth);
+    let new_text = format!("{indent}{}", emit(source, span.clone(), &indent, options)?);
+
+    minimal_edit(source, span, new_text)
+}
+
+/// Shrinks a replacement of `span` to the bytes that differ.
+fn minimal_edit(
+    source: &str,
+    span: ByteRange<usize>,
+    new_text: String,
+) -> Option<(ByteRange<usize>, String)> {
+    let old = &source[span.clone()];
+
+    let prefix = old
+        .char_indices()
+        .zip(new_text.chars())
+        .find(|((_, a), b)| a != b)
+        .map_or(old.len().min(new_text.len()), |((i, _), _)| i);
+
+    let suffix = old[prefix..]
+        .chars()
+        .rev()
+        .zip(new_text[prefix..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len_utf8())
+        .sum::<usize>();
+
+    if prefix == old.len() && prefix == new_text.len() {
+        return None;
+    }
+
+    Some((
+        span.start + prefix..span.end - suffix,
+        new_text[prefix..new_text.len() - suffix].to_owned(),
+    ))
+}
+
+/// Re-formats a span of whole tags, indenting continuation lines to `indent`.
+fn emit(
+    source: &str,
+    span: ByteRange<usize>,
+    indent: &str,
+    options: &FormatOptions,
+) -> Option<String> {
+    let tokens = full_lexer().parse(&source[span]).into_result().ok()?;
+    let formatted = format(&tokens, options);
+
+    // Lines of code blocks are kept as written
+    let formatted_tokens = full_lexer().parse(&formatted).into_result().ok()?;
+    let code = code_bodies(&formatted, &formatted_tokens);
+
+    let mut offset = 0;
+    Some(
+        formatted
+            .trim_end()
+            .lines()
+            .enumerate()
+            .map(|(i, line)| {
+                let start = offset;
+                offset += line.len() + 1;
+
+                match i {
+                    0 => line.to_owned(),
+                    _ if line.is_empty() => String::new(),
+                    _ if code.iter().any(|c| c.start < start && start < c.end) => line.to_owned(),
+                    _ => format!("{indent}{line}"),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )
+}
+
+fn line_indent(source: &str, offset: usize) -> &str {
+    let start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = &source[start..offset];
+
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// Edits renaming every `from` tag to `to`, or why `to` cannot hold the
+/// content of one of them.
+pub fn replace_tag(
+    source: &str,
+    tree: &Tree,
+    from: &str,
+    to: &TagSchema,
+) -> Result<Vec<(ByteRange<usize>, String)>, String> {
+    let mut edits = vec![];
+    collect_replacements(source, &tree.nodes, from, to, &mut edits)?;
+    Ok(edits)
+}
+
+fn collect_replacements(
+    source: &str,
+    nodes: &[Node],
+    from: &str,
+    to: &TagSchema,
+    edits: &mut Vec<(ByteRange<usize>, String)>,
+) -> Result<(), String> {
+    for node in nodes {
+        if node.name() == from {
+            check_compatible(node, to)?;
+
+            let tag = &source[node.tag_span.clone()];
+            match &node.body {
+                // `- text`
+                Body::Text { text, .. } if tag.starts_with('-') => {
+                    edits.push((node.tag_span.clone(), format!("{}: {text}", to.name)));
+                }
+                // `[[ tags ]]`
+                Body::Tags { open, close, .. } if tag == "[[" => {
+                    edits.push((open.clone(), format!("{} [", to.name)));
+                    if let Some(close) = close {
+                        edits.push((close.clone(), "]".to_owned()));
+                    }
+                }
+                _ if tag == node.name() => {
+                    edits.push((node.tag_span.clone(), to.name.to_owned()));
+                }
+                _ => {
+                    return Err(format!(
+                        "`{}` at byte {} uses shorthand syntax that cannot be renamed",
+                        node.name(),
+                        node.tag_span.start
+                    ))
+                }
+            }
+        }
+
+        collect_replacements(source, node.children(), from, to, edits)?;
+    }
+
+    Ok(())
+}
+
+fn check_compatible(node: &Node, to: &TagSchema) -> Result<(), String> {
+    let (min, max) = to.arity();
+    let found = node.arguments.len();
+
+    if found < min || found > max {
+        return Err(format!(
+            "`{}` expects {}, but a `{}` has {found}",
+            to.name,
+            to.expected_arguments(),
+            node.name()
+        ));
+    }
+
+    for (argument, schema) in node.arguments.iter().zip(to.arguments) {
+        if !schema.kind.accepts(&argument.value, argument.number) {
+            return Err(format!(
+                "`{}` does not accept {:?} as its `{}` argument",
+                to.name, argument.value, schema.name
+            ));
+        }
+    }
+
+    let accepted = match node.body {
+        Body::None => to.body.optional(),
+        Body::Text { .. } => to.body.accepts_text(),
+        Body::Tags { .. } => to.body.accepts_tags(),
+    };
+
+    if !accepted {
+        return Err(format!(
+            "`{}` cannot hold the body of a `{}`",
+            to.name,
+            node.name()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Edits undoing the recoveries of the tree builder: skipped tokens are
+/// deleted and assumed text is inserted. Also returns whether the repaired
+/// document lexes and parses without errors.
+pub fn repair(source: &str, tree: &Tree) -> (Vec<(ByteRange<usize>, String)>, bool) {
+    let mut edits = tree
+        .recoveries
+        .iter()
+        .map(|recovery| match recovery.action {
+            Action::Skipped(_) => (recovery.span.clone(), String::new()),
+            // A closing bracket after a text body would be read as part of
+            // the text, so it goes on a line of its own
+            Action::Inserted(text) if text.starts_with(']') => (
+                recovery.span.end..recovery.span.end,
+                format!("\n{}{text}", line_indent(source, recovery.span.start)),
+            ),
+            Action::Inserted(text) => (recovery.span.end..recovery.span.end, text.to_owned()),
+        })
+        .collect::<Vec<_>>();
+    edits.sort_by_key(|(span, _)| (span.start, span.end));
+
+    let mut repaired = source.to_owned();
+    for (span, text) in edits.iter().rev() {
+        repaired.replace_range(span.clone(), text);
+    }
+
+    let parses = match lexer().parse(&repaired).into_result() {
+        Ok(tokens) => !parser()
+            .parse(tokens.as_slice().spanned((0..repaired.len()).into()))
+            .has_errors(),
+        Err(_) => false,
+    };
+
+    (edits, parses)
+}
+
+/// The document without the tokens the tag tree skipped and the tags it had
+/// to complete, formatted when what is left lexes. Also returns each removed
+/// range with the reason.
+pub fn export_clean(
+    source: &str,
+    tree: &Tree,
+    options: &FormatOptions,
+) -> (String, Vec<(ByteRange<usize>, String)>) {
+    let mut removals = tree
+        .recoveries
+        .iter()
+        .map(|recovery| {
+            let span = match recovery.action {
+                Action::Skipped(_) => recovery.span.clone(),
+                Action::Inserted(_) => tree
+                    .innermost(&recovery.span)
+                    .map_or(recovery.span.clone(), |node| node.span.clone()),
+            };
+            (whole_lines(source, span), recovery.message.clone())
+        })
+        .collect::<Vec<_>>();
+    removals.sort_by_key(|(span, _)| (span.start, std::cmp::Reverse(span.end)));
+
+    // A removal inside an earlier one is already covered by it
+    let mut end = 0;
+    removals.retain(|(span, _)| {
+        let keep = span.start >= end;
+        end = end.max(span.end);
+        keep
+    });
+
+    let mut clean = source.to_owned();
+    for (span, _) in removals.iter().rev() {
+        clean.replace_range(span.clone(), "");
+    }
+
+    let formatted = match full_lexer().parse(&clean).into_result() {
+        Ok(tokens) => Some(format(&tokens, options)),
+        Err(_) => None,
+    };
+
+    (formatted.unwrap_or(clean), removals)
+}
+
+/// Widens `span` to the lines it covers when nothing else is on them.
+fn whole_lines(source: &str, span: ByteRange<usize>) -> ByteRange<usize> {
+    let start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let end = source[span.end..]
+        .find('\n')
+        .map_or(source.len(), |i| span.end + i + 1);
+
+    match source[start..span.start].trim().is_empty() && source[span.end..end].trim().is_empty() {
+        true => start..end,
+        false => span,
+    }
+}
+
+/// Edits inserting an `a` tag before each heading that is not already
+/// preceded by one, named after a slug of the heading text. Slugs taken by
+/// other anchors of the document get a numeric suffix.
+pub fn anchor_headings(source: &str, tree: &Tree) -> Vec<(ByteRange<usize>, String)> {
+    let mut taken = HashSet::new();
+    anchor_names(&tree.nodes, &mut taken);
+
+    let mut edits = vec![];
+    insert_anchors(source, &tree.nodes, &mut taken, &mut edits);
+    edits
+}
+
+fn anchor_names(nodes: &[Node], names: &mut HashSet<String>) {
+    for node in nodes {
+        if let ("a", Some(argument)) = (node.name(), node.arguments.first()) {
+            names.insert(argument.value.clone());
+        }
+        anchor_names(node.children(), names);
+    }
+}
+
+fn insert_anchors(
+    source: &str,
+    nodes: &[Node],
+    taken: &mut HashSet<String>,
+    edits: &mut Vec<(ByteRange<usize>, String)>,
+) {
+    for (i, node) in nodes.iter().enumerate() {
+        let anchored = i
+            .checked_sub(1)
+            .is_some_and(|previous| nodes[previous].name() == "a");
+
+        if let ("h", false, Body::Text { text, .. }) = (node.name(), anchored, &node.body) {
+            let base = slug(text);
+            let name = (1..)
+                .map(|n| match n {
+                    1 => base.clone(),
+                    n => format!("{base}-{n}"),
+                })
+                .find(|name| !taken.contains(name))
+                .unwrap_or(base);
+
+            let at = node.span.start;
+            edits.push((at..at, format!("a \"{name}\"\n{}", line_indent(source, at))));
+            taken.insert(name);
+        }
+
+        insert_anchors(source, node.children(), taken, edits);
+    }
+}
+
+/// Lowercase ASCII letters and digits of `text`, with runs of anything else
+/// turned into single dashes.
+fn slug(text: &str) -> String {
+    let slug = text
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+
+    match slug.is_empty() {
+        true => "heading".to_owned(),
+        false => slug,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chumsky::Parser;
+    use dalet::daleth::lexer::full_lexer;
+
+    use super::*;
+    use crate::schema::tag_schema;
+
+    /// Runs `edit` on the tree of `source` and applies the edit it returns.
+    fn edited<F>(source: &str, edit: F) -> Option<String>
+    where
+        F: Fn(&Tree) -> Option<(ByteRange<usize>, String)>,
+    {
+        let tokens = full_lexer().parse(source).into_result().unwrap();
+        let tree = Tree::new(source, &tokens);
+        let (span, new_text) = edit(&tree)?;
+
+        let mut edited = source.to_owned();
+        edited.replace_range(span, &new_text);
+        Some(edited)
+    }
+
+    fn moved(source: &str, offset: usize, up: bool) -> Option<String> {
+        edited(source, |tree| {
+            move_node(source, tree, offset, up, &FormatOptions::default())
+        })
+    }
+
+    const SIBLINGS: &str = "h1: One\np: Two\np: Three\n";
+
+    #[test]
+    fn move_node_swaps_with_a_sibling() {
+        assert_eq!(
+            moved(SIBLINGS, 8, true).as_deref(),
+            Some("p: Two\nh1: One\np: Three\n")
+        );
+        assert_eq!(
+            moved(SIBLINGS, 8, false).as_deref(),
+            Some("h1: One\np: Three\np: Two\n")
+        );
+    }
+
+    #[test]
+    fn move_node_stops_at_the_first_and_last_sibling() {
+        assert_eq!(moved(SIBLINGS, 0, true), None);
+        assert_eq!(moved(SIBLINGS, SIBLINGS.len() - 3, false), None);
+    }
+
+    #[test]
+    fn move_node_stays_inside_the_parent() {
+        let source = "row [\n    p: One\n    p: Two\n]\nh1: After\n";
+
+        assert_eq!(
+            moved(source, 10, false).as_deref(),
+            Some("row [\n    p: Two\n    p: One\n]\nh1: After\n")
+        );
+        assert_eq!(moved(source, 21, false), None);
+    }
+
+    #[test]
+    fn move_node_carries_the_children() {
+        let source = "h1: Before\nrow [\n    p: One\n]\n";
+
+        assert_eq!(
+            moved(source, 11, true).as_deref(),
+            Some("row [\n    p: One\n]\nh1: Before\n")
+        );
+    }
+
+    fn replaced(source: &str, from: &str, to: &str) -> Result<String, String> {
+        let tokens = full_lexer().parse(source).into_result().unwrap();
+        let tree = Tree::new(source, &tokens);
+        let mut edits = replace_tag(source, &tree, from, tag_schema(to).unwrap())?;
+
+        let mut replaced = source.to_owned();
+        edits.sort_by_key(|(span, _)| std::cmp::Reverse(span.start));
+        for (span, new_text) in edits {
+            replaced.replace_range(span, &new_text);
+        }
+        Ok(replaced)
+    }
+
+    #[test]
+    fn replace_tag_renames_every_occurrence() {
+        assert_eq!(
+            replaced("b: One\nrow [\n    b: Two\n]\n", "b", "i").as_deref(),
+            Ok("i: One\nrow [\n    i: Two\n]\n")
+        );
+    }
+
+    #[test]
+    fn replace_tag_refuses_other_arguments() {
+        assert_eq!(
+            replaced("b: Text\n", "b", "img"),
+            Err("`img` expects 1 argument, but a `b` has 0".to_owned())
+        );
+        assert_eq!(
+            replaced("footn 1: The note\n", "footn", "link"),
+            Err("`link` does not accept \"1\" as its `url` argument".to_owned())
+        );
+    }
+
+    #[test]
+    fn replace_tag_refuses_another_body() {
+        assert_eq!(
+            replaced("row [\n    p: Text\n]\n", "row", "b"),
+            Err("`b` cannot hold the body of a `row`".to_owned())
+        );
+    }
+
+    fn range_formatted(
+        source: &str,
+        range: ByteRange<usize>,
+    ) -> Option<(ByteRange<usize>, String)> {
+        let tokens = full_lexer().parse(source).into_result().unwrap();
+        let tree = Tree::new(source, &tokens);
+        format_range(source, &tree, range, &FormatOptions::default())
+    }
+
+    const UNFORMATTED: &str = "h1:   Keep\nrow [\np:    Fix\n]\np:   Keep\n";
+
+    #[test]
+    fn format_range_keeps_bytes_outside_the_tag() {
+        let row = 11..UNFORMATTED.len() - 11;
+        let (span, new_text) = range_formatted(UNFORMATTED, 18..20).unwrap();
+
+        assert!(row.start <= span.start && span.end <= row.end);
+        let mut formatted = UNFORMATTED.to_owned();
+        formatted.replace_range(span, &new_text);
+        assert_eq!(formatted, "h1:   Keep\nrow [\n    p: Fix\n]\np:   Keep\n");
+    }
+
+    #[test]
+    fn format_range_trims_the_edit_to_the_changed_bytes() {
+        let source = "row [\n    p:   One\n    p: Two\n]\n";
+        let (span, new_text) = range_formatted(source, 12..13).unwrap();
+
+        assert_eq!(span, 13..15);
+        assert_eq!(new_text, "");
+    }
+
+    #[test]
+    fn format_range_of_formatted_tags_is_empty() {
+        assert_eq!(range_formatted("h1: Keep\np: Text\n", 10..12), None);
+    }
+
+    /// The repaired text of `source` and whether it parses.
+    fn repaired(source: &str) -> (String, bool) {
+        let tokens = full_lexer().parse(source).into_result().unwrap();
+        let tree = Tree::new(source, &tokens);
+        let (edits, parses) = repair(source, &tree);
+
+        let mut repaired = source.to_owned();
+        for (span, text) in edits.iter().rev() {
+            repaired.replace_range(span.clone(), text);
+        }
+        (repaired, parses)
+    }
+
+    #[test]
+    fn repair_closes_brackets_on_their_own_lines() {
+        assert_eq!(
+            repaired("row [\n    row [\n        p: Text\n"),
+            (
+                "row [\n    row [\n        p: Text\n    ]\n]\n".to_owned(),
+                true
+            )
+        );
+    }
+
+    #[test]
+    fn repair_deletes_skipped_tokens_and_inserts_bodies() {
+        assert_eq!(
+            repaired("]\nh1: Notes\n"),
+            ("\nh1: Notes\n".to_owned(), true)
+        );
+        assert_eq!(
+            repaired("h1\np: Text\n"),
+            ("h1: \np: Text\n".to_owned(), true)
+        );
+    }
+
+    #[test]
+    fn repair_keeps_bodies_after_comments() {
+        let source = "row # note\n[\n    p: Text\n]\n";
+        assert_eq!(repaired(source), (source.to_owned(), true));
+    }
+
+    #[test]
+    fn repair_is_not_confident_when_errors_remain() {
+        assert_eq!(repaired("]\nimg\n"), ("\nimg\n".to_owned(), false));
+    }
+
+    fn cleaned(source: &str) -> (String, Vec<(ByteRange<usize>, String)>) {
+        let tokens = full_lexer().parse(source).into_result().unwrap();
+        export_clean(
+            source,
+            &Tree::new(source, &tokens),
+            &FormatOptions::default(),
+        )
+    }
+
+    #[test]
+    fn export_clean_reports_nested_removals_once() {
+        assert_eq!(
+            cleaned("h1: Notes\nrow [\n    h1\n"),
+            (
+                "h1: Notes\n".to_owned(),
+                vec![(10..23, "unclosed `[`".to_owned())]
+            )
+        );
+    }
+
+    #[test]
+    fn export_clean_removes_each_recovery_with_its_line() {
+        assert_eq!(
+            cleaned("]\nh1: Notes\nh1\np: Text\n"),
+            (
+                "h1: Notes\np: Text\n".to_owned(),
+                vec![
+                    (0..2, "unexpected rSquare".to_owned()),
+                    (12..15, "`h` expects a body".to_owned()),
+                ]
+            )
+        );
+    }
+
+    /// `source` with the anchors of `anchor_headings` inserted, and the number
+    /// of edits.
+    fn anchored(source: &str) -> (String, usize) {
+        let tokens = full_lexer().parse(source).into_result().unwrap();
+        let edits = anchor_headings(source, &Tree::new(source, &tokens));
+
+        let mut anchored = source.to_owned();
+        for (span, new_text) in edits.iter().rev() {
+            anchored.replace_range(span.clone(), new_text);
+        }
+        (anchored, edits.len())
+    }
+
+    #[test]
+    fn anchor_headings_suffixes_repeated_slugs() {
+        assert_eq!(
+            anchored("h1: Intro\nh2: Intro!\nrow [\n    h2: intro\n]\n").0,
+            "a \"intro\"\nh1: Intro\na \"intro-2\"\nh2: Intro!\nrow [\n    a \"intro-3\"\n    h2: intro\n]\n"
+        );
+        assert_eq!(
+            anchored("a \"intro\"\nh1: Other\nh1: Intro\n").0,
+            "a \"intro\"\nh1: Other\na \"intro-2\"\nh1: Intro\n"
+        );
+    }
+
+    #[test]
+    fn anchor_headings_changes_nothing_the_second_time() {
+        let (once, edits) = anchored("h1: Intro\nh2: Intro\np: Text\nh2: Café au lait\n");
+        assert_eq!(edits, 3);
+        assert_eq!(anchored(&once), (once, 0));
+    }
+}
@@ -0,0 +1,142 @@
+use ropey::Rope;
+use serde::Serialize;
+use tower_lsp::lsp_types::{DocumentSymbol, Range, SymbolKind};
+
+use crate::position::{span_to_range, Encoding};
+use crate::tree::Node;
+
+const DETAIL_LENGTH: usize = 40;
+
+pub fn document_symbols(nodes: &[Node], rope: &Rope, encoding: Encoding) -> Vec<DocumentSymbol> {
+    nodes
+        .iter()
+        .filter_map(|node| {
+            let children = document_symbols(node.children(), rope, encoding);
+
+            if matches!(node.name(), "br" | "hr") || (node.name() == "el" && children.is_empty()) {
+                return None;
+            }
+
+            #[allow(deprecated)]
+            Some(DocumentSymbol {
+                name: node.label(),
+                detail: node.primary_text().and_then(detail),
+                kind: symbol_kind(node.name()),
+                tags: None,
+                deprecated: None,
+                range: span_to_range(node.span.clone(), rope, encoding)?,
+                selection_range: span_to_range(node.tag_span.clone(), rope, encoding)?,
+                children: Some(children),
+            })
+        })
+        .collect()
+}
+
+/// Node of the full tag tree, keeping the kinds document symbols drop.
+#[derive(Debug, Clone, Serialize)]
+pub struct StructureNode {
+    pub kind: String,
+    pub range: Range,
+    pub children: Vec<StructureNode>,
+}
+
+pub fn structure(nodes: &[Node], rope: &Rope, encoding: Encoding) -> Vec<StructureNode> {
+    nodes
+        .iter()
+        .filter_map(|node| {
+            Some(StructureNode {
+                kind: node.label(),
+                range: span_to_range(node.span.clone(), rope, encoding)?,
+                children: structure(node.children(), rope, encoding),
+            })
+        })
+        .collect()
+}
+
+/// Entry of the breadcrumb leading to a position.
+#[derive(Debug, Serialize)]
+pub struct Crumb {
+    pub kind: SymbolKind,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub range: Range,
+}
+
+/// Symbols of `ancestors`, labelled like document symbols.
+pub fn breadcrumb(ancestors: &[&Node], rope: &Rope, encoding: Encoding) -> Vec<Crumb> {
+    ancestors
+        .iter()
+        .filter_map(|node| {
+            Some(Crumb {
+                kind: symbol_kind(node.name()),
+                name: node.label(),
+                detail: node.primary_text().and_then(detail),
+                range: span_to_range(node.span.clone(), rope, encoding)?,
+            })
+        })
+        .collect()
+}
+
+fn detail(text: &str) -> Option<String> {
+    let line = text.trim().lines().next()?.trim();
+
+    if line.chars().count() > DETAIL_LENGTH {
+        Some(format!(
+            "{}…",
+            line.chars().take(DETAIL_LENGTH - 1).collect::<String>()
+        ))
+    } else {
+        Some(line.to_owned())
+    }
+}
+
+fn symbol_kind(name: &str) -> SymbolKind {
+    match name {
+        "h" => SymbolKind::STRING,
+        "a" => SymbolKind::KEY,
+        "meta" => SymbolKind::PROPERTY,
+        "link" | "navlink" | "btn" | "navbtn" | "img" | "footlnk" => SymbolKind::FIELD,
+        "ul" | "ol" | "table" | "trow" | "tprow" | "carousel" => SymbolKind::ARRAY,
+        _ => SymbolKind::OBJECT,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chumsky::Parser;
+    use dalet::daleth::lexer::full_lexer;
+
+    use super::*;
+    use crate::tree::Tree;
+
+    fn symbols(source: &str) -> Vec<(String, Option<String>)> {
+        let tokens = full_lexer().parse(source).into_result().unwrap();
+        let tree = Tree::new(source, &tokens);
+
+        document_symbols(&tree.nodes, &Rope::from_str(source), Encoding::Utf16)
+            .into_iter()
+            .map(|symbol| (symbol.name, symbol.detail))
+            .collect()
+    }
+
+    #[test]
+    fn details_show_heading_text_and_link_url() {
+        assert_eq!(
+            symbols("h1: Notes\nlink \"https://example.com\": Example\n"),
+            [
+                ("h1".to_owned(), Some("Notes".to_owned())),
+                ("link".to_owned(), Some("https://example.com".to_owned())),
+            ]
+        );
+    }
+
+    #[test]
+    fn long_details_are_cut() {
+        let text = "word ".repeat(20);
+        let detail = symbols(&format!("p: {text}\n"))[0].1.clone().unwrap();
+
+        assert_eq!(detail.chars().count(), DETAIL_LENGTH);
+        assert!(detail.ends_with('…'));
+    }
+}
@@ -0,0 +1,151 @@
+use std::ops::Range as ByteRange;
+
+use dalet::daleth::lexer::types::Token;
+use dalet::daleth::types::Span;
+use serde::Serialize;
+use tower_lsp::lsp_types::Range;
+
+#[derive(Debug, Serialize)]
+pub struct TokenInfo {
+    pub kind: &'static str,
+    pub range: Range,
+    pub text: String,
+}
+
+pub fn tag_name(token: &Token) -> Option<&'static str> {
+    Some(match token {
+        Token::El => "el",
+        Token::H => "h",
+        Token::P => "p",
+        Token::Br => "br",
+        Token::Ul => "ul",
+        Token::Ol => "ol",
+        Token::Row => "row",
+        Token::Link => "link",
+        Token::Navlink => "navlink",
+        Token::Btn => "btn",
+        Token::Navbtn => "navbtn",
+        Token::Img => "img",
+        Token::Table => "table",
+        Token::Trow => "trow",
+        Token::Tprow => "tprow",
+        Token::Hr => "hr",
+        Token::B => "b",
+        Token::I => "i",
+        Token::Bq => "bq",
+        Token::Footlnk => "footlnk",
+        Token::Footn => "footn",
+        Token::A => "a",
+        Token::S => "s",
+        Token::Sup => "sup",
+        Token::Sub => "sub",
+        Token::Disc => "disc",
+        Token::Block => "block",
+        Token::Carousel => "carousel",
+        Token::Code => "code",
+        Token::Pre => "pre",
+        Token::Meta => "meta",
+        _ => return None,
+    })
+}
+
+pub fn token_kind(token: &Token) -> &'static str {
+    match token {
+        Token::LSquare => "lSquare",
+        Token::RSquare => "rSquare",
+        Token::ElOpen => "elOpen",
+        Token::ElClose => "elClose",
+        Token::NumberArgument(_) => "numberArgument",
+        Token::TextArgument(_) => "textArgument",
+        Token::TextBody(_) => "textBody",
+        Token::MLText(_) => "multilineText",
+        Token::MLMSText(_, _) => "multilineMinSpacesText",
+        Token::MLRText(_) => "multilineRawText",
+        Token::TextTag(_) => "textTag",
+        Token::Paragraph(_) => "paragraph",
+        Token::TableSyntax(_) => "tableSyntax",
+        Token::Comment(_) => "comment",
+        Token::EmptyLine => "emptyLine",
+        _ => "tag",
+    }
+}
+
+/// Lexer spans include the whitespace the token was padded with.
+pub fn trim_span(source: &str, span: Span) -> ByteRange<usize> {
+    let slice = &source[span.into_range()];
+    let trimmed = slice.trim();
+
+    if trimmed.is_empty() {
+        return span.into_range();
+    }
+
+    let start = span.start + (slice.len() - slice.trim_start().len());
+    start..start + trimmed.len()
+}
+
+/// Characters that can be escaped with a backslash inside `token`.
+fn escapable(token: &Token) -> &'static [char] {
+    match token {
+        Token::TextArgument(_) => &['"', '\\'],
+        Token::MLText(_) | Token::MLMSText(_, _) | Token::MLRText(_) | Token::Paragraph(_) => {
+            &['}', '\\']
+        }
+        Token::TableSyntax(_) => &['|', ']', '\\'],
+        _ => &[],
+    }
+}
+
+/// Escape sequences of a token as source spans and the characters they stand for.
+pub fn escapes(
+    source: &str,
+    token: &Token,
+    span: ByteRange<usize>,
+) -> Vec<(ByteRange<usize>, char)> {
+    let escapable = escapable(token);
+    let mut escapes = vec![];
+    let mut chars = source[span.clone()].char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        if c != '\\' {
+            continue;
+        }
+
+        match chars.next() {
+            Some((_, escaped)) if escapable.contains(&escaped) => {
+                let start = span.start + i;
+                escapes.push((start..start + 1 + escaped.len_utf8(), escaped));
+            }
+            _ => {}
+        }
+    }
+
+    escapes
+}
+
+pub fn char_name(c: char) -> &'static str {
+    match c {
+        '"' => "QUOTATION MARK",
+        '\\' => "REVERSE SOLIDUS",
+        '}' => "RIGHT CURLY BRACKET",
+        ']' => "RIGHT SQUARE BRACKET",
+        '|' => "VERTICAL LINE",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_depend_on_the_token() {
+        let source = "\"a\\\"b\\}\"";
+        let argument = Token::TextArgument("");
+
+        assert_eq!(escapes(source, &argument, 0..source.len()), [(2..4, '"')]);
+        assert_eq!(
+            escapes(source, &Token::MLText(""), 0..source.len()),
+            [(5..7, '}')]
+        );
+    }
+}
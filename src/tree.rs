@@ -0,0 +1,450 @@
+use std::ops::Range as ByteRange;
+
+use dalet::daleth::lexer::types::Token;
+use dalet::daleth::types::Spanned;
+
+use crate::schema::{token_schema, BodyKind, TagSchema};
+use crate::tokens::{token_kind, trim_span};
+
+/// Syntax tree with source spans, built directly from the token stream. The
+/// upstream parser produces typed tags without spans, which is not enough for
+/// editor features.
+#[derive(Debug, Default)]
+pub struct Tree {
+    pub nodes: Vec<Node>,
+    /// Places where the token stream did not fit the grammar and how the
+    /// builder carried on.
+    pub recoveries: Vec<Recovery>,
+}
+
+#[derive(Debug)]
+pub struct Recovery {
+    pub message: String,
+    pub span: ByteRange<usize>,
+    pub action: Action,
+}
+
+#[derive(Debug)]
+pub enum Action {
+    /// Indices of the tokens that were dropped.
+    Skipped(Vec<usize>),
+    /// Text assumed to be present at the end of the span.
+    Inserted(&'static str),
+}
+
+#[derive(Debug)]
+pub struct Node {
+    pub schema: &'static TagSchema,
+    pub span: ByteRange<usize>,
+    pub tag_span: ByteRange<usize>,
+    pub arguments: Vec<Argument>,
+    pub body: Body,
+}
+
+#[derive(Debug)]
+pub struct Argument {
+    pub value: String,
+    pub number: bool,
+    pub span: ByteRange<usize>,
+}
+
+#[derive(Debug)]
+pub enum Body {
+    None,
+    Text {
+        text: String,
+        span: ByteRange<usize>,
+    },
+    Tags {
+        children: Vec<Node>,
+        open: ByteRange<usize>,
+        close: Option<ByteRange<usize>>,
+    },
+}
+
+impl Node {
+    pub fn name(&self) -> &'static str {
+        self.schema.name
+    }
+
+    /// Tag name as written, with the heading level for headings.
+    pub fn label(&self) -> String {
+        match (self.name(), self.arguments.first()) {
+            ("h", Some(level)) => format!("h{}", level.value),
+            (name, _) => name.to_owned(),
+        }
+    }
+
+    /// Whether the schema of the tag accepts its number of arguments.
+    pub fn has_valid_arity(&self) -> bool {
+        let (min, max) = self.schema.arity();
+        (min..=max).contains(&self.arguments.len())
+    }
+
+    pub fn children(&self) -> &[Node] {
+        match &self.body {
+            Body::Tags { children, .. } => children,
+            _ => &[],
+        }
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        match &self.body {
+            Body::Text { text, .. } => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Words in the text bodies of this node and its descendants.
+    pub fn word_count(&self) -> usize {
+        let own = self
+            .text()
+            .map_or(0, |text| text.split_whitespace().count());
+        own + self.children().iter().map(Node::word_count).sum::<usize>()
+    }
+
+    /// The primary text argument, falling back to the text body.
+    pub fn primary_text(&self) -> Option<&str> {
+        self.arguments
+            .iter()
+            .find(|argument| !argument.number)
+            .map(|argument| argument.value.as_str())
+            .or_else(|| self.text())
+    }
+}
+
+impl Tree {
+    pub fn new(source: &str, tokens: &[Spanned<Token>]) -> Self {
+        let mut builder = Builder {
+            source,
+            tokens,
+            position: 0,
+            recoveries: vec![],
+        };
+
+        Self {
+            nodes: builder.nodes(None),
+            recoveries: builder.recoveries,
+        }
+    }
+
+    /// Node whose opening tag starts at `span`.
+    pub fn node_by_tag_span(&self, span: &ByteRange<usize>) -> Option<&Node> {
+        let mut nodes = self.nodes.as_slice();
+
+        loop {
+            let node = nodes
+                .iter()
+                .find(|node| node.span.start <= span.start && span.end <= node.span.end)?;
+
+            if &node.tag_span == span {
+                return Some(node);
+            }
+            nodes = node.children();
+        }
+    }
+
+    /// Innermost node containing `span`.
+    pub fn innermost(&self, span: &ByteRange<usize>) -> Option<&Node> {
+        let mut nodes = self.nodes.as_slice();
+        let mut found = None;
+
+        while let Some(node) = nodes
+            .iter()
+            .find(|node| node.span.start <= span.start && span.end <= node.span.end)
+        {
+            found = Some(node);
+            nodes = node.children();
+        }
+
+        found
+    }
+
+    /// Nodes containing `offset`, from the outermost to the innermost.
+    pub fn ancestors(&self, offset: usize) -> Vec<&Node> {
+        let mut nodes = self.nodes.as_slice();
+        let mut ancestors = vec![];
+
+        while let Some(node) = nodes
+            .iter()
+            .find(|node| node.span.start <= offset && offset <= node.span.end)
+        {
+            ancestors.push(node);
+            nodes = node.children();
+        }
+
+        ancestors
+    }
+
+    /// Siblings of the innermost node containing `offset`, and its index among them.
+    pub fn siblings_at(&self, offset: usize) -> Option<(&[Node], usize)> {
+        let mut siblings = self.nodes.as_slice();
+        let mut found = None;
+
+        while let Some(index) = siblings
+            .iter()
+            .position(|node| node.span.start <= offset && offset <= node.span.end)
+        {
+            found = Some((siblings, index));
+            siblings = siblings[index].children();
+        }
+
+        found
+    }
+}
+
+struct Builder<'a, 'src> {
+    source: &'a str,
+    tokens: &'a [Spanned<Token<'src>>],
+    position: usize,
+    recoveries: Vec<Recovery>,
+}
+
+impl<'a, 'src> Builder<'a, 'src> {
+    fn peek(&mut self) -> Option<&'a Token<'src>> {
+        while let Some((token, _)) = self.tokens.get(self.position) {
+            if !matches!(token, Token::Comment(_) | Token::EmptyLine) {
+                return Some(token);
+            }
+            self.position += 1;
+        }
+
+        None
+    }
+
+    /// Drops a token that cannot start a tag, merging it into the previous
+    /// recovery if that one skipped the token just before.
+    fn skip(&mut self) {
+        let index = self.position;
+        let span = self.span(index);
+        self.position += 1;
+
+        if let Some(Recovery {
+            span: last_span,
+            action: Action::Skipped(skipped),
+            ..
+        }) = self.recoveries.last_mut()
+        {
+            if skipped.last() == Some(&(index - 1)) {
+                skipped.push(index);
+                last_span.end = span.end;
+                return;
+            }
+        }
+
+        self.recoveries.push(Recovery {
+            message: format!("unexpected {}", token_kind(&self.tokens[index].0)),
+            span,
+            action: Action::Skipped(vec![index]),
+        });
+    }
+
+    fn span(&self, index: usize) -> ByteRange<usize> {
+        trim_span(self.source, self.tokens[index].1)
+    }
+
+    fn nodes(&mut self, close: Option<&Token>) -> Vec<Node> {
+        let mut nodes = vec![];
+
+        while let Some(token) = self.peek() {
+            if Some(token) == close {
+                break;
+            }
+
+            match self.node() {
+                Some(node) => nodes.push(node),
+                None => self.skip(),
+            }
+        }
+
+        nodes
+    }
+
+    fn node(&mut self) -> Option<Node> {
+        let start = self.position;
+        let token = &self.tokens[start].0;
+        let schema = token_schema(token)?;
+        let tag_span = self.span(start);
+        self.position += 1;
+
+        let body = match token {
+            Token::TextTag(text) | Token::Paragraph(text) => Body::Text {
+                text: text.to_string(),
+                span: tag_span.clone(),
+            },
+            Token::TableSyntax(_) => Body::None,
+            Token::ElOpen => self.tags(start, &Token::ElClose),
+            _ => {
+                let arguments = self.arguments();
+                let body = self.body(schema);
+
+                return Some(self.finish(schema, tag_span, arguments, body));
+            }
+        };
+
+        Some(self.finish(schema, tag_span, vec![], body))
+    }
+
+    fn finish(
+        &self,
+        schema: &'static TagSchema,
+        tag_span: ByteRange<usize>,
+        arguments: Vec<Argument>,
+        body: Body,
+    ) -> Node {
+        let end = match &body {
+            Body::Tags {
+                close: Some(close), ..
+            } => close.end,
+            Body::Tags { children, open, .. } => {
+                children.last().map_or(open.end, |child| child.span.end)
+            }
+            Body::Text { span, .. } => span.end,
+            Body::None => arguments.last().map_or(tag_span.end, |a| a.span.end),
+        };
+
+        Node {
+            schema,
+            span: tag_span.start..end.max(tag_span.end),
+            tag_span,
+            arguments,
+            body,
+        }
+    }
+
+    fn arguments(&mut self) -> Vec<Argument> {
+        let mut arguments = vec![];
+
+        while let Some(token) = self.tokens.get(self.position).map(|(t, _)| t) {
+            let (value, number) = match token {
+                Token::TextArgument(t) => (t.to_string(), false),
+                Token::NumberArgument(n) => (n.to_string(), true),
+                _ => break,
+            };
+
+            arguments.push(Argument {
+                value,
+                number,
+                span: self.span(self.position),
+            });
+            self.position += 1;
+        }
+
+        arguments
+    }
+
+    fn body(&mut self, schema: &TagSchema) -> Body {
+        if schema.body == BodyKind::None {
+            return Body::None;
+        }
+
+        // A missing body is inserted after the tag, before any comment that
+        // follows it
+        let end = self.span(self.position - 1).end;
+        let missing = |builder: &mut Self| {
+            if !schema.body.optional() {
+                builder.recoveries.push(Recovery {
+                    message: format!("`{}` expects a body", schema.name),
+                    span: end..end,
+                    action: Action::Inserted(if schema.body.accepts_text() {
+                        ": "
+                    } else {
+                        " [ ]"
+                    }),
+                });
+            }
+            Body::None
+        };
+
+        let Some(token) = self.peek() else {
+            return missing(self);
+        };
+
+        let text = match token {
+            Token::LSquare => {
+                let open = self.position;
+                self.position += 1;
+                return self.tags(open, &Token::RSquare);
+            }
+            Token::TextBody(t) | Token::MLText(t) | Token::MLMSText(_, t) | Token::MLRText(t) => {
+                t.to_string()
+            }
+            _ => return missing(self),
+        };
+
+        let span = self.span(self.position);
+        self.position += 1;
+
+        Body::Text { text, span }
+    }
+
+    fn tags(&mut self, open: usize, close: &Token) -> Body {
+        let children = self.nodes(Some(close));
+        let open = self.span(open);
+
+        let close = match self.peek() {
+            Some(token) if token == close => {
+                let span = self.span(self.position);
+                self.position += 1;
+                Some(span)
+            }
+            _ => {
+                let end = children.last().map_or(open.end, |child| child.span.end);
+                self.recoveries.push(Recovery {
+                    message: format!("unclosed `{}`", &self.source[open.clone()]),
+                    span: open.start..end,
+                    action: Action::Inserted(if close == &Token::ElClose { "]]" } else { "]" }),
+                });
+                None
+            }
+        };
+
+        Body::Tags {
+            children,
+            open,
+            close,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chumsky::Parser;
+    use dalet::daleth::lexer::full_lexer;
+
+    use super::*;
+
+    fn tree(source: &str) -> Tree {
+        let tokens = full_lexer().parse(source).into_result().unwrap();
+        Tree::new(source, &tokens)
+    }
+
+    #[test]
+    fn word_count_includes_descendants() {
+        let tree = tree("row [\n    p: Two words\n    row [\n        - Three more words\n    ]\n    img \"not.png\"\n]\n");
+
+        assert_eq!(tree.nodes[0].word_count(), 5);
+        assert_eq!(tree.nodes[0].children()[1].word_count(), 3);
+    }
+
+    #[test]
+    fn comments_between_a_tag_and_its_body_are_skipped() {
+        let tree = tree("row # note\n\n[\n    p: Text\n]\n");
+
+        assert!(tree.recoveries.is_empty(), "{:?}", tree.recoveries);
+        assert_eq!(tree.nodes[0].children()[0].name(), "p");
+    }
+
+    #[test]
+    fn missing_bodies_are_inserted_before_comments() {
+        let tree = tree("h1 # note\np: Text\n");
+
+        let spans = tree
+            .recoveries
+            .iter()
+            .map(|recovery| (recovery.message.as_str(), recovery.span.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(spans, [("`h` expects a body", 2..2)]);
+        assert_eq!(tree.nodes[1].name(), "p");
+    }
+}
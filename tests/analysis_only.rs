@@ -0,0 +1,81 @@
+mod common;
+
+use common::{text_document, text_document_position, uri, Server};
+use serde_json::{json, Value};
+
+fn server() -> (Server, String) {
+    let mut server = Server::initialized(json!({ "analysisOnly": true }));
+    let document = uri("analysis.dlt");
+    server.open_and_wait(&document, "a \"intro\"\nrow [\np: Text\n]\n");
+    (server, document)
+}
+
+fn assert_refused(response: &Value) {
+    assert_eq!(response["error"]["code"], -32600, "{response}");
+    assert_eq!(
+        response["error"]["message"],
+        "Edits are disabled in analysis-only mode"
+    );
+}
+
+#[test]
+fn formatting_is_refused() {
+    let (mut server, document) = server();
+    let options = json!({ "tabSize": 4, "insertSpaces": true });
+
+    let mut params = text_document(&document);
+    params["options"] = options.clone();
+    assert_refused(&server.request("textDocument/formatting", params));
+
+    let mut params = text_document(&document);
+    params["options"] = options;
+    params["range"] = json!({
+        "start": { "line": 1, "character": 0 },
+        "end": { "line": 3, "character": 1 },
+    });
+    assert_refused(&server.request("textDocument/rangeFormatting", params));
+}
+
+#[test]
+fn rename_is_refused() {
+    let (mut server, document) = server();
+
+    let mut params = text_document_position(&document, 0, 4);
+    params["newName"] = json!("start");
+    assert_refused(&server.request("textDocument/rename", params));
+}
+
+#[test]
+fn editing_commands_are_refused() {
+    let (mut server, document) = server();
+    let position = json!({ "line": 1, "character": 0 });
+
+    for (command, arguments) in [
+        ("daleth.formatDocument", json!([document])),
+        ("daleth.moveNodeUp", json!([document, position])),
+        ("daleth.moveNodeDown", json!([document, position])),
+        ("daleth.replaceTag", json!([document, "row", "bq"])),
+        ("daleth.anchorHeadings", json!([document])),
+    ] {
+        assert_refused(&server.command(command, arguments));
+    }
+    assert_refused(&server.request("daleth/suggestRepair", json!({ "uri": document })));
+    assert_refused(&server.request(
+        "daleth/expandAbbreviation",
+        json!({ "uri": document, "position": position, "abbreviation": "ul>-" }),
+    ));
+
+    assert!(!server
+        .received
+        .iter()
+        .any(|m| m["method"] == "workspace/applyEdit"));
+}
+
+#[test]
+fn formatting_is_not_advertised() {
+    let mut server = Server::start();
+    let capabilities = server.initialize(json!({}), json!({ "analysisOnly": true }), None);
+
+    assert!(capabilities.get("documentFormattingProvider").is_none());
+    assert!(capabilities.get("renameProvider").is_none());
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use std::process::{Command, Output};
+
+use common::Workspace;
+use serde_json::Value;
+
+fn check(args: &[&str], workspace: &Workspace) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dalet_lsp"))
+        .arg("--check")
+        .args(args)
+        .current_dir(&workspace.root)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn workspace() -> Workspace {
+    Workspace::new(&[
+        ("clean.dlt", "h1: Notes\n"),
+        ("broken.dlt", "h1: Notes\nimg\n"),
+    ])
+}
+
+#[test]
+fn clean_files_exit_with_zero() {
+    let output = check(&["clean.dlt"], &workspace());
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn human_output_lists_errors() {
+    let output = check(&["clean.dlt", "broken.dlt"], &workspace());
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stdout(&output),
+        "broken.dlt:1:1: error: found end of input expected Text argument\n\
+         broken.dlt:2:1: error: tag `img` expects 1 argument, found 0\n"
+    );
+}
+
+#[test]
+fn github_output_annotates_errors() {
+    let output = check(&["--format-output", "github", "broken.dlt"], &workspace());
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stdout(&output),
+        "::error file=broken.dlt,line=1,col=1,endLine=3,endColumn=1::found end of input expected Text argument\n\
+         ::error file=broken.dlt,line=2,col=1,endLine=2,endColumn=4::tag `img` expects 1 argument, found 0\n"
+    );
+}
+
+#[test]
+fn json_output_is_one_array() {
+    let output = check(
+        &["--format-output", "json", "clean.dlt", "broken.dlt"],
+        &workspace(),
+    );
+    assert_eq!(output.status.code(), Some(1));
+
+    let json: Value = serde_json::from_str(&stdout(&output)).unwrap();
+    let entries = json.as_array().unwrap();
+    let codes = entries
+        .iter()
+        .map(|entry| {
+            (
+                entry["file"].as_str().unwrap(),
+                entry["diagnostic"]["code"].as_str().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        codes,
+        [
+            ("broken.dlt", "parse-error"),
+            ("broken.dlt", "argument-count")
+        ]
+    );
+    assert_eq!(entries[1]["diagnostic"]["range"]["start"]["line"], 1);
+}
+
+#[test]
+fn json_output_of_clean_files_is_empty() {
+    let output = check(&["--format-output", "json", "clean.dlt"], &workspace());
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output).trim(), "[]");
+}
+
+#[test]
+fn bad_usage_exits_with_two() {
+    let workspace = workspace();
+
+    assert_eq!(check(&[], &workspace).status.code(), Some(2));
+    assert_eq!(
+        check(&["--format-output", "xml", "clean.dlt"], &workspace)
+            .status
+            .code(),
+        Some(2)
+    );
+}
+
+#[test]
+fn missing_files_are_errors() {
+    let output = check(&["missing.dlt"], &workspace());
+
+    assert_eq!(output.status.code(), Some(1));
+}
@@ -0,0 +1,155 @@
+mod common;
+
+use common::{document_edits, uri, Server};
+use serde_json::{json, Value};
+
+const UNFORMATTED: &str = "row [\np: Text\n]\n";
+
+/// Answers the edits the server applies with `answers`, in order.
+fn answering(answers: Vec<bool>) -> Server {
+    let mut server = Server::initialized(json!({}));
+    let mut answers = answers.into_iter();
+    server.respond = Box::new(move |method, _| match method {
+        "workspace/applyEdit" => json!({ "applied": answers.next().unwrap() }),
+        _ => Value::Null,
+    });
+    server
+}
+
+fn applied_edits(server: &Server) -> Vec<&Value> {
+    server
+        .received
+        .iter()
+        .filter(|m| m["method"] == "workspace/applyEdit")
+        .collect()
+}
+
+#[test]
+fn rejected_edit_is_retried() {
+    let mut server = answering(vec![false, true]);
+    let document = uri("format.dlt");
+    server.open_and_wait(&document, UNFORMATTED);
+
+    let response = server.command("daleth.formatDocument", json!([document]));
+
+    assert_eq!(response["result"], true);
+    let edits = applied_edits(&server);
+    assert_eq!(edits.len(), 2);
+    assert_eq!(edits[0]["params"], edits[1]["params"]);
+    assert_eq!(
+        document_edits(&edits[1]["params"]["edit"])[0]["newText"],
+        "row [\n    p: Text\n]\n"
+    );
+}
+
+#[test]
+fn edit_rejected_twice_is_given_up() {
+    let mut server = answering(vec![false, false]);
+    let document = uri("format.dlt");
+    server.open_and_wait(&document, UNFORMATTED);
+
+    let response = server.command("daleth.formatDocument", json!([document]));
+
+    assert_eq!(response["result"], false);
+    assert_eq!(applied_edits(&server).len(), 2);
+    let message = server.wait_for(|m| m["method"] == "window/showMessage");
+    assert_eq!(
+        message["params"]["message"],
+        "The edit was rejected by the editor"
+    );
+}
+
+#[test]
+fn incompatible_replace_tag_is_refused() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("replace.dlt");
+    server.open_and_wait(&document, "row [\n    p: Text\n]\n");
+
+    let response = server.command("daleth.replaceTag", json!([document, "row", "b"]));
+
+    assert_eq!(response["error"]["code"], -32602);
+    assert_eq!(
+        response["error"]["message"],
+        "`b` cannot hold the body of a `row`"
+    );
+}
+
+fn parse_report(text: &str) -> Value {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("report.dlt");
+    server.open_and_wait(&document, text);
+
+    server.command("daleth.parseReport", json!([document]))["result"].clone()
+}
+
+fn range(start: (u32, u32), end: (u32, u32)) -> Value {
+    json!({
+        "start": { "line": start.0, "character": start.1 },
+        "end": { "line": end.0, "character": end.1 },
+    })
+}
+
+#[test]
+fn parse_report_merges_skipped_tokens() {
+    let report = parse_report("]\n]\nh1: Notes\n");
+
+    assert_eq!(report["stage"], "tree");
+    assert_eq!(
+        report["errors"],
+        json!([{
+            "action": "skipped",
+            "message": "unexpected rSquare",
+            "range": range((0, 0), (1, 1)),
+            "tokens": [
+                { "kind": "rSquare", "text": "]" },
+                { "kind": "rSquare", "text": "]" },
+            ],
+        }])
+    );
+}
+
+#[test]
+fn parse_report_assumes_closing_brackets() {
+    let report = parse_report("row [\n    p: Text\n");
+
+    assert_eq!(
+        report["errors"],
+        json!([{
+            "action": "inserted",
+            "message": "unclosed `[`",
+            "range": range((0, 4), (1, 11)),
+            "text": "]",
+        }])
+    );
+}
+
+#[test]
+fn parse_report_assumes_missing_bodies() {
+    let report = parse_report("h1\np: Text\n");
+
+    assert_eq!(
+        report["errors"],
+        json!([{
+            "action": "inserted",
+            "message": "`h` expects a body",
+            "range": range((0, 2), (0, 2)),
+            "text": ": ",
+        }])
+    );
+}
+
+#[test]
+fn parse_report_stops_at_lexer_errors() {
+    let report = parse_report("h1 Notes\n");
+
+    assert_eq!(report["stage"], "lexer");
+    assert_eq!(report["errors"][0]["action"], "stopped");
+    assert_eq!(report["errors"][0]["range"], range((0, 3), (0, 4)));
+}
+
+#[test]
+fn parse_report_of_a_valid_document_is_empty() {
+    let report = parse_report("h1: Notes\nrow [\n    p: Text\n]\n");
+
+    assert_eq!(report, json!({ "stage": "tree", "errors": [] }));
+}
@@ -0,0 +1,378 @@
+//! Drives the server binary over stdio like an editor would.
+
+// Every test file uses only part of the helpers
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Answers a request from the server, given its method and params.
+pub type Responder = Box<dyn FnMut(&str, &Value) -> Value>;
+
+pub struct Server {
+    child: Child,
+    stdin: ChildStdin,
+    messages: Receiver<Value>,
+    next_id: i64,
+    responses: HashMap<i64, Value>,
+    /// Notifications and requests sent by the server and not yet waited for.
+    pub received: Vec<Value>,
+    pub respond: Responder,
+}
+
+impl Server {
+    pub fn start() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_dalet_lsp"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to start the server");
+
+        let stdin = child.stdin.take().unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let (sender, messages) = channel();
+
+        std::thread::spawn(move || loop {
+            let mut length = None;
+            loop {
+                let mut header = String::new();
+                if stdout.read_line(&mut header).unwrap_or(0) == 0 {
+                    return;
+                }
+                match header.trim().strip_prefix("Content-Length: ") {
+                    Some(value) => length = value.parse().ok(),
+                    None if header.trim().is_empty() => break,
+                    None => {}
+                }
+            }
+
+            let mut body = vec![0; length.expect("missing Content-Length")];
+            if stdout.read_exact(&mut body).is_err() {
+                return;
+            }
+            if sender.send(serde_json::from_slice(&body).unwrap()).is_err() {
+                return;
+            }
+        });
+
+        Self {
+            child,
+            stdin,
+            messages,
+            next_id: 0,
+            responses: HashMap::new(),
+            received: vec![],
+            respond: Box::new(|method, _| match method {
+                "workspace/applyEdit" => json!({ "applied": true }),
+                _ => Value::Null,
+            }),
+        }
+    }
+
+    /// A started server, initialized with `options` and no client capabilities.
+    pub fn initialized(options: Value) -> Self {
+        let mut server = Self::start();
+        server.initialize(json!({}), options, None);
+        server
+    }
+
+    /// Sends `initialize` and `initialized`, returning the server capabilities.
+    pub fn initialize(
+        &mut self,
+        capabilities: Value,
+        options: Value,
+        root: Option<&Path>,
+    ) -> Value {
+        let folders = root.map(|root| json!([{ "uri": file_uri(root), "name": "workspace" }]));
+        let result = self.result(
+            "initialize",
+            json!({
+                "capabilities": capabilities,
+                "initializationOptions": options,
+                "workspaceFolders": folders,
+            }),
+        );
+        self.notify("initialized", json!({}));
+
+        result["capabilities"].clone()
+    }
+
+    fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    pub fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    /// Sends a request without waiting for its response. Null `params` are left
+    /// out.
+    pub fn send_request(&mut self, method: &str, params: Value) -> i64 {
+        self.next_id += 1;
+        let id = self.next_id;
+        let mut request = json!({ "jsonrpc": "2.0", "id": id, "method": method });
+        if !params.is_null() {
+            request["params"] = params;
+        }
+
+        self.send(request);
+        id
+    }
+
+    /// The whole response to a request, with either `result` or `error`.
+    pub fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.send_request(method, params);
+        self.response(id)
+    }
+
+    pub fn response(&mut self, id: i64) -> Value {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            if let Some(response) = self.responses.remove(&id) {
+                return response;
+            }
+            self.pump(deadline);
+        }
+    }
+
+    /// The result of a request, which must not fail.
+    pub fn result(&mut self, method: &str, params: Value) -> Value {
+        let response = self.request(method, params);
+        assert!(
+            response.get("error").is_none(),
+            "{method} failed: {response}"
+        );
+        response["result"].clone()
+    }
+
+    pub fn command(&mut self, command: &str, arguments: Value) -> Value {
+        self.request(
+            "workspace/executeCommand",
+            json!({ "command": command, "arguments": arguments }),
+        )
+    }
+
+    /// Reads one message, answering it if it is a request.
+    fn pump(&mut self, deadline: Instant) {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let message = self
+            .messages
+            .recv_timeout(timeout)
+            .expect("timed out waiting for the server");
+        self.handle(message);
+    }
+
+    fn handle(&mut self, message: Value) {
+        match (message.get("method"), message.get("id")) {
+            (Some(method), Some(id)) => {
+                let result = (self.respond)(method.as_str().unwrap(), &message["params"]);
+                let id = id.clone();
+                self.received.push(message);
+                self.send(json!({ "jsonrpc": "2.0", "id": id, "result": result }));
+            }
+            (Some(_), None) => self.received.push(message),
+            (None, Some(id)) => {
+                self.responses.insert(id.as_i64().unwrap(), message);
+            }
+            (None, None) => panic!("unexpected message {message}"),
+        }
+    }
+
+    /// Removes and returns the first message from the server matching
+    /// `predicate`, waiting for it if needed.
+    pub fn wait_for(&mut self, predicate: impl Fn(&Value) -> bool) -> Value {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            if let Some(i) = self.received.iter().position(&predicate) {
+                return self.received.remove(i);
+            }
+            self.pump(deadline);
+        }
+    }
+
+    /// Reads every message that arrives within `duration`.
+    pub fn drain(&mut self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        while let Ok(message) = self
+            .messages
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            self.handle(message);
+        }
+    }
+
+    /// The diagnostics of the next publish for `uri`.
+    pub fn diagnostics(&mut self, uri: &str) -> Vec<Value> {
+        let publish = self.wait_for(|m| {
+            m["method"] == "textDocument/publishDiagnostics" && m["params"]["uri"] == uri
+        });
+        publish["params"]["diagnostics"].as_array().unwrap().clone()
+    }
+
+    /// Messages of the `window/logMessage` notifications received so far.
+    pub fn logs(&self) -> Vec<String> {
+        self.received
+            .iter()
+            .filter(|m| m["method"] == "window/logMessage")
+            .filter_map(|m| m["params"]["message"].as_str().map(ToOwned::to_owned))
+            .collect()
+    }
+
+    pub fn open(&mut self, uri: &str, text: &str) {
+        self.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": { "uri": uri, "languageId": "daleth", "version": 1, "text": text }
+            }),
+        );
+    }
+
+    /// Opens a document and waits for its first diagnostics.
+    pub fn open_and_wait(&mut self, uri: &str, text: &str) -> Vec<Value> {
+        self.open(uri, text);
+        self.diagnostics(uri)
+    }
+
+    pub fn change(&mut self, uri: &str, text: &str, version: i32) {
+        self.notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": uri, "version": version },
+                "contentChanges": [{ "text": text }],
+            }),
+        );
+    }
+
+    pub fn close(&mut self, uri: &str) {
+        self.notify(
+            "textDocument/didClose",
+            json!({ "textDocument": { "uri": uri } }),
+        );
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// URI of a document that does not exist on disk.
+pub fn uri(name: &str) -> String {
+    format!("file:///dalet-lsp-tests/{name}")
+}
+
+pub fn file_uri(path: &Path) -> String {
+    format!("file://{}", path.display())
+}
+
+pub fn position(line: u32, character: u32) -> Value {
+    json!({ "line": line, "character": character })
+}
+
+pub fn text_document(uri: &str) -> Value {
+    json!({ "textDocument": { "uri": uri } })
+}
+
+pub fn text_document_position(uri: &str, line: u32, character: u32) -> Value {
+    json!({ "textDocument": { "uri": uri }, "position": position(line, character) })
+}
+
+/// Edits of the only document changed by a versioned workspace edit.
+pub fn document_edits(edit: &Value) -> Vec<Value> {
+    edit["documentChanges"][0]["edits"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Applies LSP text edits to `text`, with columns counted in UTF-16.
+pub fn apply_edits(text: &str, edits: &[Value]) -> String {
+    let offset = |position: &Value| {
+        let line = position["line"].as_u64().unwrap() as usize;
+        let character = position["character"].as_u64().unwrap() as usize;
+
+        let line_start = text
+            .split_inclusive('\n')
+            .take(line)
+            .map(str::len)
+            .sum::<usize>();
+        let mut units = 0;
+        let column = text[line_start..]
+            .char_indices()
+            .find(|(_, c)| {
+                let found = units >= character;
+                units += c.len_utf16();
+                found
+            })
+            .map_or(text.len() - line_start, |(i, _)| i);
+
+        line_start + column
+    };
+
+    let mut edits = edits
+        .iter()
+        .map(|edit| {
+            (
+                offset(&edit["range"]["start"]),
+                offset(&edit["range"]["end"]),
+                edit["newText"].as_str().unwrap().to_owned(),
+            )
+        })
+        .collect::<Vec<_>>();
+    edits.sort_by_key(|(start, _, _)| std::cmp::Reverse(*start));
+
+    let mut text = text.to_owned();
+    for (start, end, new_text) in edits {
+        text.replace_range(start..end, &new_text);
+    }
+    text
+}
+
+/// Directory of files on disk, removed when dropped.
+pub struct Workspace {
+    pub root: PathBuf,
+}
+
+impl Workspace {
+    pub fn new(files: &[(&str, &str)]) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join(format!(
+            "dalet-lsp-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        for (name, text) in files {
+            let path = root.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+        std::fs::create_dir_all(&root).unwrap();
+
+        Self { root }
+    }
+
+    pub fn uri(&self, name: &str) -> String {
+        file_uri(&self.root.join(name))
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
@@ -0,0 +1,190 @@
+mod common;
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use common::{apply_edits, uri, Server, Workspace};
+use serde_json::{json, Value};
+
+fn lines(diagnostics: &[Value]) -> Vec<u64> {
+    diagnostics
+        .iter()
+        .map(|d| d["range"]["start"]["line"].as_u64().unwrap())
+        .collect()
+}
+
+#[test]
+fn visible_diagnostics_are_published_first() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("large.dlt");
+    server.notify(
+        "daleth/setVisibleRange",
+        json!({
+            "uri": document,
+            "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 10, "character": 0 } },
+        }),
+    );
+
+    server.open(&document, &format!("img\n{}img\n", "p: Text\n".repeat(40)));
+
+    let visible = server.diagnostics(&document);
+    let all = server.diagnostics(&document);
+    assert_eq!(lines(&visible), [0, 1]);
+    assert_eq!(lines(&all), [0, 1, 41]);
+}
+
+/// Lines of the diagnostics file once it has `count` of them.
+fn sink_lines(path: &Path, count: usize) -> Vec<Value> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let lines = std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect::<Vec<Value>>();
+        if lines.len() >= count || Instant::now() > deadline {
+            return lines;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn file_sink_writes_a_line_per_publish() {
+    let workspace = Workspace::new(&[]);
+    let path = workspace.root.join("diagnostics.jsonl");
+    let mut server = Server::initialized(json!({
+        "diagnosticsSink": "file",
+        "diagnosticsFile": path,
+    }));
+    let document = uri("sink.dlt");
+
+    server.open(&document, "img\n");
+    sink_lines(&path, 1);
+    server.change(&document, "h1: Fixed\n", 2);
+
+    let lines = sink_lines(&path, 2);
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["uri"], document.as_str());
+    assert_eq!(lines[0]["version"], 1);
+    assert_ne!(lines[0]["diagnostics"], json!([]));
+    assert_eq!(lines[1]["version"], 2);
+    assert_eq!(lines[1]["diagnostics"], json!([]));
+
+    server.drain(Duration::from_millis(200));
+    assert!(!server
+        .received
+        .iter()
+        .any(|m| m["method"] == "textDocument/publishDiagnostics"));
+}
+
+#[test]
+fn both_sink_writes_the_file_and_publishes() {
+    let workspace = Workspace::new(&[]);
+    let path = workspace.root.join("diagnostics.jsonl");
+    let mut server = Server::initialized(json!({
+        "diagnosticsSink": "both",
+        "diagnosticsFile": path,
+    }));
+    let document = uri("sink.dlt");
+
+    let published = server.open_and_wait(&document, "img\n");
+
+    let lines = sink_lines(&path, 1);
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["diagnostics"], Value::Array(published));
+}
+
+#[test]
+fn file_sink_without_a_file_warns() {
+    let mut server = Server::initialized(json!({ "diagnosticsSink": "file" }));
+    let document = uri("sink.dlt");
+
+    server.open(&document, "img\n");
+
+    let warning = server.wait_for(|m| {
+        m["method"] == "window/logMessage"
+            && m["params"]["message"]
+                .as_str()
+                .is_some_and(|message| message.starts_with("failed to write diagnostics"))
+    });
+    assert_eq!(
+        warning["params"]["message"],
+        "failed to write diagnostics: diagnosticsFile is not set"
+    );
+    assert_eq!(warning["params"]["type"], 2);
+}
+
+#[test]
+fn typographic_quick_fix_replaces_the_character() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("quotes.dlt");
+    let text = "img \u{201C}a.png\u{201D}\n";
+    let hints = server
+        .open_and_wait(&document, text)
+        .into_iter()
+        .filter(|d| d["code"] == "typographic-character")
+        .collect::<Vec<_>>();
+    assert_eq!(hints.len(), 2);
+
+    let actions = server.result(
+        "textDocument/codeAction",
+        json!({
+            "textDocument": { "uri": document },
+            "range": hints[0]["range"],
+            "context": { "diagnostics": hints },
+        }),
+    );
+
+    let edits = actions
+        .as_array()
+        .unwrap()
+        .iter()
+        .flat_map(|action| {
+            assert_eq!(action["kind"], "quickfix");
+            action["edit"]["changes"][&document]
+                .as_array()
+                .unwrap()
+                .clone()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(apply_edits(text, &edits), "img \"a.png\"\n");
+}
+
+#[test]
+fn unused_anchor_quick_fix_removes_the_line() {
+    let workspace = Workspace::new(&[
+        ("page.dlt", "a \"intro\"\na \"kept\"\nh1: Notes\n"),
+        ("links.dlt", "link \"page.dlt#kept\": Kept\n"),
+    ]);
+    let mut server = Server::start();
+    server.initialize(
+        json!({}),
+        json!({ "unusedAnchors": true }),
+        Some(&workspace.root),
+    );
+
+    let document = workspace.uri("page.dlt");
+    let text = std::fs::read_to_string(workspace.root.join("page.dlt")).unwrap();
+    let hints = server
+        .open_and_wait(&document, &text)
+        .into_iter()
+        .filter(|d| d["code"] == "unused-anchor")
+        .collect::<Vec<_>>();
+    assert_eq!(hints.len(), 1);
+    assert_eq!(hints[0]["message"], "anchor `intro` is never linked to");
+
+    let actions = server.result(
+        "textDocument/codeAction",
+        json!({
+            "textDocument": { "uri": document },
+            "range": hints[0]["range"],
+            "context": { "diagnostics": hints },
+        }),
+    );
+    let edits = actions[0]["edit"]["changes"][&document]
+        .as_array()
+        .unwrap()
+        .clone();
+    assert_eq!(apply_edits(&text, &edits), "a \"kept\"\nh1: Notes\n");
+}
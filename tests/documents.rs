@@ -0,0 +1,92 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{text_document, text_document_position, uri, Server};
+use serde_json::{json, Value};
+
+#[test]
+fn unassociated_documents_are_skipped_until_associated() {
+    let mut server = Server::initialized(json!({}));
+    let text = uri("notes.txt");
+    let daleth = uri("notes.dlt");
+
+    server.open(&text, "h1 Notes");
+    server.open_and_wait(&daleth, "h1 Notes");
+    server.drain(Duration::from_millis(200));
+
+    assert!(!server
+        .received
+        .iter()
+        .any(|m| m["params"]["uri"] == text.as_str()));
+    assert!(server
+        .logs()
+        .contains(&format!("skipping {text}: extension is not associated")));
+
+    server.notify(
+        "workspace/didChangeConfiguration",
+        json!({ "settings": { "associatedExtensions": ["dlt", "txt"] } }),
+    );
+    server.change(&text, "h1 Notes", 2);
+    server.diagnostics(&text);
+}
+
+/// Asserts that `response` is the error for a document that was never opened.
+fn assert_not_open(response: &Value, document: &str) {
+    assert_eq!(response["error"]["code"], -32001, "{response}");
+    assert_eq!(
+        response["error"]["data"],
+        json!({ "reason": "documentNotOpen", "uri": document })
+    );
+}
+
+#[test]
+fn requests_on_unopened_documents_fail_with_document_not_open() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("unopened.dlt");
+
+    assert_not_open(
+        &server.request(
+            "textDocument/hover",
+            text_document_position(&document, 0, 0),
+        ),
+        &document,
+    );
+    assert_not_open(
+        &server.request("textDocument/documentSymbol", text_document(&document)),
+        &document,
+    );
+    assert_not_open(
+        &server.command("daleth.parseReport", json!([document])),
+        &document,
+    );
+    assert_not_open(
+        &server.request("daleth/suggestRepair", json!({ "uri": document })),
+        &document,
+    );
+}
+
+#[test]
+fn closed_documents_are_no_longer_open() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("closed.dlt");
+    server.open_and_wait(&document, "h1 Notes");
+    server.close(&document);
+
+    assert_not_open(
+        &server.request("textDocument/foldingRange", text_document(&document)),
+        &document,
+    );
+}
+
+#[test]
+fn unassociated_unopened_documents_get_no_result() {
+    let mut server = Server::initialized(json!({}));
+    let response = server.request(
+        "textDocument/hover",
+        text_document_position(&uri("notes.txt"), 0, 0),
+    );
+
+    assert!(response.get("error").is_none(), "{response}");
+    assert_eq!(response["result"], Value::Null);
+}
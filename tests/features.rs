@@ -0,0 +1,85 @@
+mod common;
+
+use common::{text_document, text_document_position, uri, Server};
+use serde_json::{json, Value};
+
+const PROVIDERS: [&str; 8] = [
+    "hoverProvider",
+    "completionProvider",
+    "documentSymbolProvider",
+    "semanticTokensProvider",
+    "documentFormattingProvider",
+    "documentRangeFormattingProvider",
+    "codeActionProvider",
+    "renameProvider",
+];
+
+fn capabilities(options: Value) -> Value {
+    Server::start().initialize(json!({}), options, None)
+}
+
+#[test]
+fn enabled_features_are_advertised() {
+    let capabilities = capabilities(json!({}));
+
+    for provider in PROVIDERS {
+        assert!(capabilities.get(provider).is_some(), "{provider}");
+    }
+}
+
+#[test]
+fn disabled_features_are_not_advertised() {
+    let capabilities = capabilities(json!({
+        "features": {
+            "hover": false,
+            "completion": false,
+            "documentSymbol": false,
+            "semanticTokens": false,
+            "formatting": false,
+            "rangeFormatting": false,
+            "codeAction": false,
+            "rename": false,
+        }
+    }));
+
+    for provider in PROVIDERS {
+        assert!(capabilities.get(provider).is_none(), "{provider}");
+    }
+}
+
+/// Results of hover, completion, rename and formatting on a small document.
+fn results(options: Value) -> Vec<Value> {
+    let mut server = Server::initialized(options);
+    let document = uri("features.dlt");
+    server.open_and_wait(&document, "a \"intro\"\nrow [\np: Text\n]\n");
+
+    let position = text_document_position(&document, 1, 0);
+    let mut rename = text_document_position(&document, 0, 4);
+    rename["newName"] = json!("other");
+    let mut formatting = text_document(&document);
+    formatting["options"] = json!({ "tabSize": 4, "insertSpaces": true });
+
+    vec![
+        server.result("textDocument/hover", position.clone()),
+        server.result("textDocument/completion", position),
+        server.result("textDocument/rename", rename),
+        server.result("textDocument/formatting", formatting),
+    ]
+}
+
+#[test]
+fn disabled_features_return_nothing() {
+    let enabled = results(json!({}));
+    assert!(
+        enabled.iter().all(|result| !result.is_null()),
+        "{enabled:?}"
+    );
+
+    let results = results(json!({
+        "features": { "hover": false, "completion": false, "formatting": false, "rename": false }
+    }));
+    assert_eq!(
+        results,
+        [Value::Null, Value::Null, Value::Null, Value::Null]
+    );
+}
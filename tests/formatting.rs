@@ -0,0 +1,109 @@
+mod common;
+
+use common::{position, uri, Server};
+use serde_json::{json, Value};
+
+const DOCUMENT: &str =
+    "meta \"title\": Notes\nmeta \"by\": Me\n\nh1: Notes\nrow [\n    p: Text\n]\n";
+
+#[test]
+fn preview_formats_returns_every_style() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("preview.dlt");
+    server.open_and_wait(&document, DOCUMENT);
+
+    let response = server.command("daleth.previewFormats", json!([document]));
+    let styles = response["result"].as_object().unwrap();
+
+    let mut names = styles.keys().map(String::as_str).collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, ["aligned", "compact", "default", "expanded"]);
+
+    assert_ne!(styles["compact"], styles["expanded"]);
+    assert_ne!(styles["default"], styles["aligned"]);
+    assert!(styles["aligned"]
+        .as_str()
+        .unwrap()
+        .starts_with("meta \"by\":    Me\nmeta \"title\": Notes\n"));
+}
+
+#[test]
+fn cancelled_range_formatting_returns_no_edit() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("large.dlt");
+    server.open_and_wait(&document, &"row [\np: Text\n]\n".repeat(2000));
+
+    let id = server.send_request(
+        "textDocument/rangeFormatting",
+        json!({
+            "textDocument": { "uri": document },
+            "range": { "start": position(0, 0), "end": position(3, 0) },
+            "options": { "tabSize": 4, "insertSpaces": true },
+        }),
+    );
+    server.notify("$/cancelRequest", json!({ "id": id }));
+
+    let response = server.response(id);
+    assert_eq!(response["error"]["code"], -32800);
+    assert!(response.get("result").is_none());
+}
+
+#[test]
+fn formatting_that_is_changed_or_cancelled_returns_no_edit() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("changed.dlt");
+    let text = "row [\np: Text\n]\n".repeat(2000);
+    server.open_and_wait(&document, &text);
+
+    let id = server.send_request(
+        "textDocument/formatting",
+        json!({
+            "textDocument": { "uri": document },
+            "options": { "tabSize": 4, "insertSpaces": true },
+        }),
+    );
+    server.change(&document, &format!("h1: Notes\n{text}"), 2);
+    server.notify("$/cancelRequest", json!({ "id": id }));
+
+    // Whichever checkpoint sees it first, the edit of the old text is dropped
+    let response = server.response(id);
+    let code = &response["error"]["code"];
+    assert!(code == -32801 || code == -32800, "{response}");
+    assert!(response.get("result").is_none());
+}
+
+/// Range of the edit formatting `text` when `encoding` is negotiated.
+fn formatting_range(encoding: &str, text: &str) -> Value {
+    let mut server = Server::start();
+    let capabilities = server.initialize(
+        json!({ "general": { "positionEncodings": [encoding] } }),
+        json!({}),
+        None,
+    );
+    assert_eq!(capabilities["positionEncoding"], encoding);
+
+    let document = uri("encoding.dlt");
+    server.open_and_wait(&document, text);
+    let edits = server.result(
+        "textDocument/formatting",
+        json!({
+            "textDocument": { "uri": document },
+            "options": { "tabSize": 4, "insertSpaces": true },
+        }),
+    );
+    edits[0]["range"].clone()
+}
+
+#[test]
+fn formatting_covers_the_whole_document_in_the_negotiated_encoding() {
+    // The last line has 12 characters: 17 bytes and 13 UTF-16 code units
+    let text = "h1: Notes\np:   Grüße 😀";
+
+    for (encoding, end) in [("utf-8", 17), ("utf-16", 13)] {
+        assert_eq!(
+            formatting_range(encoding, text),
+            json!({ "start": position(0, 0), "end": position(1, end) }),
+            "{encoding}"
+        );
+    }
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use common::{text_document_position, uri, Server};
+use serde_json::{json, Value};
+
+fn hover(capabilities: Value) -> Value {
+    let mut server = Server::start();
+    server.initialize(capabilities, json!({}), None);
+
+    let document = uri("hover.dlt");
+    server.open_and_wait(&document, "h1: Notes\n");
+    server.result(
+        "textDocument/hover",
+        text_document_position(&document, 0, 0),
+    )["contents"]
+        .clone()
+}
+
+#[test]
+fn hover_uses_markdown_when_the_client_supports_it() {
+    let contents = hover(json!({
+        "textDocument": { "hover": { "contentFormat": ["markdown", "plaintext"] } }
+    }));
+
+    assert_eq!(contents["kind"], "markdown");
+    assert!(contents["value"]
+        .as_str()
+        .unwrap()
+        .starts_with("```daleth\n"));
+}
+
+/// Asserts that `contents` is plain text without Markdown markers.
+fn assert_plaintext(contents: &Value) {
+    assert_eq!(contents["kind"], "plaintext");
+    let value = contents["value"].as_str().unwrap();
+    assert!(!value.contains(['`', '*']), "{value:?}");
+    assert!(!value.contains("```"), "{value:?}");
+}
+
+#[test]
+fn hover_falls_back_to_plaintext() {
+    assert_plaintext(&hover(json!({
+        "textDocument": { "hover": { "contentFormat": ["plaintext"] } }
+    })));
+    assert_plaintext(&hover(json!({})));
+}
+
+#[test]
+fn hover_decodes_escape_sequences() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("escapes.dlt");
+    server.open_and_wait(&document, "img \"a\\\"b\"\ncode \"rust\" {x\\}y}\n");
+
+    let mut hover = |line, character| {
+        server.result(
+            "textDocument/hover",
+            text_document_position(&document, line, character),
+        )
+    };
+
+    let argument = hover(0, 6);
+    assert_eq!(
+        argument["contents"]["value"],
+        "Escape sequence \\\"\n\n\" QUOTATION MARK (U+0022)"
+    );
+    assert_eq!(argument["range"]["start"]["character"], 6);
+    assert_eq!(argument["range"]["end"]["character"], 8);
+
+    let body = hover(1, 15);
+    assert_eq!(
+        body["contents"]["value"],
+        "Escape sequence \\}\n\n} RIGHT CURLY BRACKET (U+007D)"
+    );
+    assert_eq!(body["range"]["start"]["character"], 14);
+}
+
+#[test]
+fn hover_on_a_block_counts_its_words() {
+    let mut server = Server::initialized(json!({ "readingWordsPerMinute": 0 }));
+    let document = uri("words.dlt");
+    server.open_and_wait(&document, "row [\n    p: Three short words\n]\n");
+
+    let hover = server.result(
+        "textDocument/hover",
+        text_document_position(&document, 0, 1),
+    );
+    assert!(hover["contents"]["value"]
+        .as_str()
+        .unwrap()
+        .ends_with("\n\n3 words"));
+}
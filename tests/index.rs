@@ -0,0 +1,79 @@
+mod common;
+
+use common::{text_document_position, Server, Workspace};
+use serde_json::{json, Value};
+
+#[test]
+fn idle_index_is_rebuilt_from_disk() {
+    let workspace = Workspace::new(&[("page.dlt", "a \"intro\"\nh1: Intro\n")]);
+    let mut server = Server::start();
+    server.initialize(
+        json!({}),
+        json!({ "indexIdleUnloadMinutes": 0.001 }),
+        Some(&workspace.root),
+    );
+
+    let index = workspace.uri("index.dlt");
+    server.open_and_wait(&index, "link \"page.dlt#intro\": Intro\n");
+    let definition = text_document_position(&index, 0, 17);
+
+    let location = server.result("textDocument/definition", definition.clone());
+    assert_eq!(location["uri"], workspace.uri("page.dlt"));
+    assert_eq!(location["range"]["start"]["line"], 1);
+
+    std::fs::write(
+        workspace.root.join("page.dlt"),
+        "h1: Page\n\na \"intro\"\nh1: Intro\n",
+    )
+    .unwrap();
+    server.wait_for(|m| m["params"]["message"] == "unloaded idle workspace index");
+
+    let location = server.result("textDocument/definition", definition);
+    assert_eq!(location["range"]["start"]["line"], 3);
+}
+
+fn location(uri: &str, start: (u32, u32), end: (u32, u32)) -> Value {
+    json!({
+        "uri": uri,
+        "range": {
+            "start": { "line": start.0, "character": start.1 },
+            "end": { "line": end.0, "character": end.1 },
+        },
+    })
+}
+
+#[test]
+fn declaration_and_definition_point_at_different_parts_of_an_anchor() {
+    const PAGE: &str = "a \"intro\"\nh1: Intro\na \"end\"\n";
+    const LINKS: &str = "link \"page.dlt#intro\": Go\n";
+    let workspace = Workspace::new(&[("page.dlt", PAGE), ("links.dlt", LINKS)]);
+    let mut server = Server::start();
+    server.initialize(json!({}), json!({}), Some(&workspace.root));
+
+    let (page, links) = (workspace.uri("page.dlt"), workspace.uri("links.dlt"));
+    server.open_and_wait(&page, PAGE);
+    server.open_and_wait(&links, LINKS);
+
+    for (uri, line, character) in [(&links, 0, 17), (&page, 0, 4)] {
+        let position = text_document_position(uri, line, character);
+        assert_eq!(
+            server.result("textDocument/declaration", position.clone()),
+            location(&page, (0, 3), (0, 8))
+        );
+        assert_eq!(
+            server.result("textDocument/definition", position),
+            location(&page, (1, 0), (1, 9))
+        );
+    }
+
+    // The last anchor marks nothing after it, so it is its own definition
+    let position = text_document_position(&page, 2, 4);
+    assert_eq!(
+        server.result("textDocument/declaration", position.clone()),
+        location(&page, (2, 3), (2, 6))
+    );
+    assert_eq!(
+        server.result("textDocument/definition", position),
+        location(&page, (2, 0), (2, 7))
+    );
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use common::Server;
+use serde_json::{json, Value};
+
+fn server() -> Server {
+    let mut server = Server::start();
+    server.initialize(
+        json!({
+            "textDocument": {
+                "formatting": { "dynamicRegistration": true },
+                "semanticTokens": {
+                    "dynamicRegistration": true,
+                    "requests": { "full": true },
+                    "tokenTypes": [],
+                    "tokenModifiers": [],
+                    "formats": ["relative"],
+                },
+            }
+        }),
+        json!({}),
+        None,
+    );
+    server
+}
+
+/// Methods of the next `method` request, which is `client/registerCapability`
+/// or `client/unregisterCapability`.
+fn next(server: &mut Server, method: &str) -> Vec<Value> {
+    let request = server.wait_for(|m| m["method"] == method);
+    let key = match method {
+        "client/registerCapability" => "registrations",
+        _ => "unregisterations",
+    };
+    request["params"][key].as_array().unwrap().clone()
+}
+
+fn methods(registrations: &[Value]) -> Vec<&str> {
+    let mut methods = registrations
+        .iter()
+        .map(|registration| registration["method"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    methods.sort();
+    methods
+}
+
+#[test]
+fn disabling_semantic_tokens_unregisters_them() {
+    let mut server = server();
+    let registered = next(&mut server, "client/registerCapability");
+    assert_eq!(
+        methods(&registered),
+        ["textDocument/formatting", "textDocument/semanticTokens"]
+    );
+
+    server.notify(
+        "workspace/didChangeConfiguration",
+        json!({ "settings": { "enableSemanticTokens": false } }),
+    );
+    let unregistered = next(&mut server, "client/unregisterCapability");
+    assert_eq!(methods(&unregistered), ["textDocument/semanticTokens"]);
+
+    server.drain(std::time::Duration::from_millis(200));
+    assert!(!server
+        .received
+        .iter()
+        .any(|m| m["method"] == "client/registerCapability"));
+}
+
+#[test]
+fn changing_extensions_registers_the_new_selector() {
+    let mut server = server();
+    next(&mut server, "client/registerCapability");
+
+    server.notify(
+        "workspace/didChangeConfiguration",
+        json!({ "settings": { "associatedExtensions": ["dlt", "daleth"] } }),
+    );
+    let unregistered = next(&mut server, "client/unregisterCapability");
+    assert_eq!(
+        methods(&unregistered),
+        ["textDocument/formatting", "textDocument/semanticTokens"]
+    );
+
+    let registered = next(&mut server, "client/registerCapability");
+    assert_eq!(
+        methods(&registered),
+        ["textDocument/formatting", "textDocument/semanticTokens"]
+    );
+    for registration in &registered {
+        assert_eq!(
+            registration["registerOptions"]["documentSelector"],
+            json!([{ "pattern": "**/*.{dlt,daleth}" }])
+        );
+    }
+}
@@ -0,0 +1,244 @@
+mod common;
+
+use common::{apply_edits, document_edits, text_document_position, uri, Server};
+use serde_json::{json, Value};
+
+fn range(start: (u32, u32), end: (u32, u32)) -> Value {
+    json!({
+        "start": { "line": start.0, "character": start.1 },
+        "end": { "line": end.0, "character": end.1 },
+    })
+}
+
+#[test]
+fn tokens_have_kinds_and_ranges() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("tokens.dlt");
+    server.open_and_wait(&document, "# note\nh1: Notes\n");
+
+    let tokens = server.result("daleth/tokens", json!({ "uri": document }));
+    assert_eq!(
+        tokens,
+        json!([
+            { "kind": "comment", "range": range((0, 0), (0, 6)), "text": "# note" },
+            { "kind": "tag", "range": range((1, 0), (1, 1)), "text": "h" },
+            { "kind": "numberArgument", "range": range((1, 1), (1, 2)), "text": "1" },
+            { "kind": "textBody", "range": range((1, 2), (1, 9)), "text": ": Notes" },
+        ])
+    );
+}
+
+#[test]
+fn tokens_report_lexer_errors() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("tokens.dlt");
+    server.open_and_wait(&document, "h1 Notes\n");
+
+    let tokens = server.result("daleth/tokens", json!({ "uri": document }));
+    assert_eq!(
+        tokens,
+        json!([{ "kind": "error", "range": range((0, 3), (0, 4)), "text": "N" }])
+    );
+}
+
+#[test]
+fn all_diagnostics_skips_closed_documents() {
+    let mut server = Server::initialized(json!({}));
+    let broken = uri("broken.dlt");
+    let closed = uri("closed.dlt");
+    server.open_and_wait(&broken, "img\n");
+    server.open_and_wait(&closed, "h1: Notes\n");
+
+    let all = server.result("daleth/allDiagnostics", json!(null));
+    let mut uris = all.as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+    uris.sort();
+    assert_eq!(uris, [broken.clone(), closed.clone()]);
+
+    server.close(&closed);
+
+    let all = server.result("daleth/allDiagnostics", json!(null));
+    let all = all.as_object().unwrap();
+    assert_eq!(all.keys().collect::<Vec<_>>(), [&broken]);
+    assert_ne!(all[&broken], json!([]));
+}
+
+fn full_tokens(server: &mut Server, document: &str) -> Value {
+    server.result(
+        "textDocument/semanticTokens/full",
+        json!({ "textDocument": { "uri": document } }),
+    )
+}
+
+fn delta_tokens(server: &mut Server, document: &str, previous: &Value) -> Value {
+    server.result(
+        "textDocument/semanticTokens/full/delta",
+        json!({ "textDocument": { "uri": document }, "previousResultId": previous }),
+    )
+}
+
+#[test]
+fn unchanged_version_gets_an_empty_delta() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("delta.dlt");
+    server.open_and_wait(&document, "h1: Notes\n");
+
+    let full = full_tokens(&mut server, &document);
+    let delta = delta_tokens(&mut server, &document, &full["resultId"]);
+
+    assert_eq!(delta, json!({ "resultId": full["resultId"], "edits": [] }));
+}
+
+#[test]
+fn changed_version_gets_edits_under_a_new_id() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("delta.dlt");
+    server.open_and_wait(&document, "h1: Notes\n");
+
+    let full = full_tokens(&mut server, &document);
+    server.change(&document, "h1: Notes\nbr\n", 2);
+    server.diagnostics(&document);
+    let delta = delta_tokens(&mut server, &document, &full["resultId"]);
+
+    assert_ne!(delta["resultId"], full["resultId"]);
+    assert!(!delta["edits"].as_array().unwrap().is_empty(), "{delta}");
+    assert!(delta.get("data").is_none());
+}
+
+#[test]
+fn stale_or_unknown_id_gets_full_tokens() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("delta.dlt");
+    server.open_and_wait(&document, "h1: Notes\n");
+
+    let stale = full_tokens(&mut server, &document);
+    let last = full_tokens(&mut server, &document);
+
+    for previous in [stale["resultId"].clone(), json!("unknown")] {
+        let delta = delta_tokens(&mut server, &document, &previous);
+
+        assert_eq!(delta["data"], last["data"]);
+        assert!(delta.get("edits").is_none());
+        assert_ne!(delta["resultId"], last["resultId"]);
+    }
+}
+
+#[test]
+fn suggest_repair_returns_an_edit_and_its_confidence() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("repair.dlt");
+    let text = "row [\n    p: Text\n";
+    server.open_and_wait(&document, text);
+
+    let repair = server.result("daleth/suggestRepair", json!({ "uri": document }));
+    assert_eq!(repair["confidence"], "high");
+    assert_eq!(
+        apply_edits(text, &document_edits(&repair["edit"])),
+        "row [\n    p: Text\n]\n"
+    );
+
+    server.change(&document, "]\nimg\n", 2);
+    server.diagnostics(&document);
+    let repair = server.result("daleth/suggestRepair", json!({ "uri": document }));
+    assert_eq!(repair["confidence"], "low");
+    assert_eq!(
+        repair["note"],
+        "The repaired document still has syntax errors"
+    );
+}
+
+fn structure_kinds(server: &mut Server, document: &str) -> Vec<Value> {
+    let nodes = server.result("daleth/structure", json!({ "uri": document }));
+    nodes
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|node| node["kind"].clone())
+        .collect()
+}
+
+#[test]
+fn structure_follows_new_versions() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("structure.dlt");
+    server.open_and_wait(&document, "h1: Notes\n");
+    assert_eq!(structure_kinds(&mut server, &document), [json!("h1")]);
+
+    server.change(&document, "h2: Notes\nbr\n", 2);
+    server.diagnostics(&document);
+    assert_eq!(
+        structure_kinds(&mut server, &document),
+        [json!("h2"), json!("br")]
+    );
+}
+
+#[test]
+fn structure_of_a_reopened_document_is_rebuilt() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("structure.dlt");
+    server.open_and_wait(&document, "h1: Notes\n");
+    assert_eq!(structure_kinds(&mut server, &document), [json!("h1")]);
+
+    // The reopened document starts over at the same version
+    server.open_and_wait(&document, "br\n");
+    assert_eq!(structure_kinds(&mut server, &document), [json!("br")]);
+
+    server.close(&document);
+    server.open_and_wait(&document, "p: Text\n");
+    assert_eq!(structure_kinds(&mut server, &document), [json!("p")]);
+}
+
+#[test]
+fn completion_closes_brackets_in_a_large_document_that_does_not_lex() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("large.dlt");
+    let text = format!(
+        "h1 Notes\n{}row [\n    ul [\n        - Apples\n    ]\n    ",
+        "p: Text\n".repeat(20_000)
+    );
+    let diagnostics = server.open_and_wait(&document, &text);
+    assert!(!diagnostics.is_empty());
+
+    let items = server.result(
+        "textDocument/completion",
+        text_document_position(&document, 20_005, 4),
+    );
+    let labels = items
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(labels[0], "close row");
+    assert!(labels.contains(&"ul"));
+}
+
+fn crumbs(server: &mut Server, document: &str, line: u32, character: u32) -> Vec<Value> {
+    let params = json!({ "uri": document, "position": { "line": line, "character": character } });
+    let crumbs = server.result("daleth/breadcrumb", params);
+    crumbs
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|crumb| json!([crumb["name"], crumb["range"]["start"]["line"]]))
+        .collect()
+}
+
+#[test]
+fn breadcrumb_lists_ancestors_from_the_outermost() {
+    let mut server = Server::initialized(json!({}));
+    let document = uri("breadcrumb.dlt");
+    server.open_and_wait(
+        &document,
+        "row [\n    ul [\n        - Apples\n    ]\n]\np: Text\n",
+    );
+
+    assert_eq!(
+        crumbs(&mut server, &document, 2, 12),
+        [json!(["row", 0]), json!(["ul", 1]), json!(["el", 2])]
+    );
+    assert_eq!(
+        crumbs(&mut server, &document, 3, 4),
+        [json!(["row", 0]), json!(["ul", 1])]
+    );
+    assert_eq!(crumbs(&mut server, &document, 5, 3), [json!(["p", 5])]);
+}